@@ -147,6 +147,27 @@ export function useSampoCore() {
       }
       return { roadId: r, areaIds: areas, distanceMeters: dist }
     },
+    async snapToRoad(lat: number, lng: number): Promise<{ roadId: string, lat: number, lng: number, segmentIndex: number, distanceAlongMeters: number, fraction: number, distanceMeters: number } | null> {
+      await ensureLoaded()
+      if (typeof mod.snap_to_road !== 'function') return null
+      const json: string = mod.snap_to_road(lat, lng)
+      try {
+        return JSON.parse(json)
+      } catch (e) {
+        console.warn('[WASM] failed to parse snap_to_road json', json, e)
+        return null
+      }
+    },
+    async roadGraph(): Promise<{ toleranceMeters: number, nodes: { id: number, lat: number, lng: number, degree: number, roadIds: string[] }[], edges: { id: number, roadId: string, from: number, to: number, startVertex: number, endVertex: number, lengthMeters: number }[] } | null> {
+      await ensureLoaded()
+      if (typeof mod.road_graph_json !== 'function') return null
+      try {
+        return JSON.parse(mod.road_graph_json())
+      } catch (e) {
+        console.warn('[WASM] failed to parse road_graph_json', e)
+        return null
+      }
+    },
     // ---- Game bridge (Rust側へ移譲するための雛形) ----
     async gameLoadProfile(json: any): Promise<void> {
       await ensureLoaded()
@@ -171,7 +171,10 @@ class AudioEngineProcessor extends AudioWorkletProcessor {
             try {
               const arr = new (globalThis.Array)();
               for (const ch of channels) arr.push(ch);
-              this.wasm.audio_register_asset(bufferId, sampleRate, arr);
+              // Convert mismatched-rate assets offline unless the sender opts out
+              if (msg.layout && typeof this.wasm.audio_register_asset_layout === 'function') this.wasm.audio_register_asset_layout(bufferId, sampleRate, arr, msg.layout, msg.resample !== false);
+              else if (msg.resample !== false && typeof this.wasm.audio_register_asset_resampled === 'function') this.wasm.audio_register_asset_resampled(bufferId, sampleRate, arr);
+              else this.wasm.audio_register_asset(bufferId, sampleRate, arr);
             } catch (e) {
               this._dbg('audio_register_asset failed', { error: String(e) })
             }
@@ -191,6 +194,13 @@ class AudioEngineProcessor extends AudioWorkletProcessor {
         });
         if (options?.lpf?.enabled) this._updateLPF(busId, options.lpf.cutoffHz ?? 1000, options.lpf.q ?? 0.707);
         this._dbg('createBus', { busId, gainDb })
+        // wasm createBus
+        // eslint-disable-next-line @typescript-eslint/no-floating-promises
+        (async () => {
+          if (await this._ensureWasm()) {
+            try { if (typeof this.wasm.audio_create_bus === 'function') this.wasm.audio_create_bus(busId, gainDb) } catch (e) {}
+          }
+        })();
         break;
       }
       case 'createTrack': {
@@ -199,7 +209,7 @@ class AudioEngineProcessor extends AudioWorkletProcessor {
         const buf = this.buffers.get(assetId);
         if (!buf) break;
         const pan = clamp(options?.pan ?? 0, -1, 1);
-        const { l, r } = this._panGains(pan);
+        const { l, r } = this._panGains(pan, (buf.channels?.length ?? 1) > 1);
         const gainDb = options?.gainDb ?? 0;
         this.tracks.set(trackId, {
           id: trackId,
@@ -213,6 +223,7 @@ class AudioEngineProcessor extends AudioWorkletProcessor {
           panL: l,
           panR: r,
           gain: new Ramp(dBToLin(gainDb)),
+          gainBeforeStop: null, // user gain to restore after a fade-out stop
           loop: null,
           notifyEnded: false,
           pendingSwitch: null,
@@ -250,12 +261,21 @@ class AudioEngineProcessor extends AudioWorkletProcessor {
         break;
       }
       case 'stop': {
-        const { trackId, whenSamples } = msg;
+        const { trackId, whenSamples, fadeOutMs } = msg;
         if (!this.tracks.has(trackId)) break;
         const w = Number.isFinite(whenSamples) ? Math.max(0, whenSamples|0) : this.currentSample;
-        this.events.push({ type: 'stop', when: w, trackId });
+        this.events.push({ type: 'stop', when: w, trackId, fadeOutMs: fadeOutMs ?? 0 });
         this._dbg('queue stop', { when: w })
-        this._dbg('stop', { trackId, when: w })
+        this._dbg('stop', { trackId, when: w, fadeOutMs })
+        // wasm stop
+        // eslint-disable-next-line @typescript-eslint/no-floating-promises
+        (async () => {
+          if (await this._ensureWasm()) {
+            // `w` is on the worklet clock: carry over the delay from now onto the engine's clock
+            const delay = Number.isFinite(whenSamples) ? Math.max(0, w - this.currentSample) : 0;
+            try { if (typeof this.wasm.audio_stop === 'function') this.wasm.audio_stop(trackId, this.wasm.audio_current_sample() + delay, fadeOutMs ?? 0) } catch (e) {}
+          }
+        })();
         break;
       }
       case 'setGain': {
@@ -269,15 +289,35 @@ class AudioEngineProcessor extends AudioWorkletProcessor {
           if (b) b.gain.setTarget(target, samples);
         } else if (scope === 'track') {
           const t = id ? this.tracks.get(id) : null;
-          if (t) t.gain.setTarget(target, samples);
+          // While fading out, the new gain applies from the next start
+          if (t?.stopping) t.gainBeforeStop = target;
+          else if (t) t.gain.setTarget(target, samples);
         }
         this._dbg('setGain', { scope, id, gainDb, rampMs })
+        // wasm setGain
+        // eslint-disable-next-line @typescript-eslint/no-floating-promises
+        (async () => {
+          if (await this._ensureWasm()) {
+            try {
+              if (scope === 'track' && typeof this.wasm.audio_set_track_gain === 'function') this.wasm.audio_set_track_gain(id, gainDb ?? 0, rampMs ?? 0)
+              else if (scope === 'bus' && typeof this.wasm.audio_set_bus_gain === 'function') this.wasm.audio_set_bus_gain(id, gainDb ?? 0, rampMs ?? 0)
+              else if (scope === 'master' && typeof this.wasm.audio_set_master_gain === 'function') this.wasm.audio_set_master_gain(gainDb ?? 0, rampMs ?? 0)
+            } catch (e) {}
+          }
+        })();
         break;
       }
       case 'setLPF': {
-        const { scope, id, cutoffHz, rampMs } = msg;
+        const { scope, id, cutoffHz, q, rampMs, order } = msg;
         if (scope === 'bus') this._updateLPF(id, cutoffHz ?? 0, 0.707);
         this._dbg('setLPF', { scope, id, cutoffHz })
+        // wasm setLPF (RBJ biquad, 12/24 dB)
+        // eslint-disable-next-line @typescript-eslint/no-floating-promises
+        (async () => {
+          if (await this._ensureWasm()) {
+            try { if (typeof this.wasm.audio_set_lpf === 'function') this.wasm.audio_set_lpf(scope, id, cutoffHz ?? 0, q ?? 0.707, rampMs ?? 0, order ?? 1) } catch (e) {}
+          }
+        })();
         break;
       }
       case 'setDucker': {
@@ -310,7 +350,66 @@ class AudioEngineProcessor extends AudioWorkletProcessor {
         // eslint-disable-next-line @typescript-eslint/no-floating-promises
         (async () => {
           if (await this._ensureWasm()) {
-            try { this.wasm.audio_set_ducker(targetBusId, keyBusId, params?.thresholdDb ?? -24, params?.ratio ?? 6, params?.attackMs ?? 10, params?.releaseMs ?? 200, params?.maxAttenDb ?? 12, params?.makeupDb ?? 0) } catch (e) {}
+            try {
+              // keyBusIds/holdMs/detector/keyCombine are honoured by the Rust mixer only
+              if (typeof this.wasm.audio_apply_commands === 'function') this.wasm.audio_apply_commands(JSON.stringify(msg))
+              else this.wasm.audio_set_ducker(targetBusId, keyBusId, params?.thresholdDb ?? -24, params?.ratio ?? 6, params?.attackMs ?? 10, params?.releaseMs ?? 200, params?.maxAttenDb ?? 12, params?.makeupDb ?? 0)
+            } catch (e) {}
+          }
+        })();
+        break;
+      }
+      case 'setPolyphony': {
+        // Enforced by the Rust mixer only
+        const { busId, maxVoices, policy } = msg;
+        // eslint-disable-next-line @typescript-eslint/no-floating-promises
+        (async () => {
+          if (await this._ensureWasm()) {
+            try { if (typeof this.wasm.audio_set_polyphony === 'function') this.wasm.audio_set_polyphony(busId, Math.max(0, maxVoices|0), policy ?? 'dropOldest') } catch (e) {}
+          }
+        })();
+        break;
+      }
+      case 'setInterpolation': {
+        // Rust mixer only: the JS mixer always interpolates linearly
+        const { trackId, mode } = msg;
+        // eslint-disable-next-line @typescript-eslint/no-floating-promises
+        (async () => {
+          if (await this._ensureWasm()) {
+            try {
+              if (trackId && typeof this.wasm.audio_set_track_interpolation === 'function') this.wasm.audio_set_track_interpolation(trackId, mode ?? 'default')
+              else if (!trackId && typeof this.wasm.audio_set_interpolation === 'function') this.wasm.audio_set_interpolation(mode ?? 'linear')
+            } catch (e) {}
+          }
+        })();
+        break;
+      }
+      case 'unloadBuffer': {
+        // JS tracks hold the buffer object itself, so dropping the id only blocks new tracks
+        if (msg.bufferId) this.buffers.delete(msg.bufferId);
+        // eslint-disable-next-line @typescript-eslint/no-floating-promises
+        (async () => {
+          if (await this._ensureWasm() && typeof this.wasm.audio_unload_asset === 'function') {
+            try { this.wasm.audio_unload_asset(msg.bufferId, msg.defer !== false) } catch (e) {}
+          }
+        })();
+        break;
+      }
+      case 'setMemoryBudget':
+      case 'removeDucker':
+      case 'setTempo':
+      case 'createGroup':
+      case 'setGroupMarkers':
+      case 'groupPlay':
+      case 'groupStop':
+      case 'groupTransition':
+      case 'setStemGains': {
+        // Ducker removal, tempo maps and music groups (stems on one playhead) exist in the Rust
+        // mixer only; the message schema is the same one audio_apply_commands parses
+        // eslint-disable-next-line @typescript-eslint/no-floating-promises
+        (async () => {
+          if (await this._ensureWasm() && typeof this.wasm.audio_apply_commands === 'function') {
+            try { this.wasm.audio_apply_commands(JSON.stringify(msg)) } catch (e) {}
           }
         })();
         break;
@@ -365,7 +464,7 @@ class AudioEngineProcessor extends AudioWorkletProcessor {
         break;
       }
       case 'transition': {
-        const { trackId, at, toAssetId, loop } = msg;
+        const { trackId, at, toAssetId, loop, mode: switchMode } = msg;
         const t = this.tracks.get(trackId);
         if (!t) break;
         if (at === 'loopEnd') {
@@ -403,7 +502,16 @@ class AudioEngineProcessor extends AudioWorkletProcessor {
             const start = (loop?.start ?? 0) >>> 0;
             const end = (loop?.end == null) ? -1 : (loop.end >>> 0);
             const xfadeMs = (loop?.crossfadeMs ?? 0) >>> 0;
-            try { this.wasm.audio_transition(trackId, at || 'now', toAssetId, mode, start, end, xfadeMs) } catch (e) {}
+            // `{ bar: N }` (bars from 1) becomes the engine's `bar:N`; nextBeat/nextBar are Rust-only
+            const atName = (at && typeof at === 'object' && at.bar != null) ? `bar:${at.bar >>> 0}` : (at || 'now');
+            try {
+              // Crossfaded switches are rendered by the Rust mixer only (the JS mixer cuts)
+              if (switchMode?.kind === 'xfade' && typeof this.wasm.audio_transition_fade_at === 'function') {
+                this.wasm.audio_transition_fade_at(trackId, 0, atName, toAssetId, mode, start, end, xfadeMs, Math.max(0, switchMode.xfadeMs ?? 0), switchMode.align ?? 'before');
+              } else {
+                this.wasm.audio_transition(trackId, atName, toAssetId, mode, start, end, xfadeMs);
+              }
+            } catch (e) {}
           }
         })();
         break;
@@ -411,6 +519,36 @@ class AudioEngineProcessor extends AudioWorkletProcessor {
       case 'query': {
         if (msg.what === 'time') {
           __dbg({ type: 'time', currentSample: this.currentSample });
+        } else if (msg.what === 'position') {
+          // eslint-disable-next-line @typescript-eslint/no-floating-promises
+          (async () => {
+            if (await this._ensureWasm() && typeof this.wasm.audio_musical_position === 'function') {
+              try {
+                const pos = JSON.parse(this.wasm.audio_musical_position(msg.trackId));
+                this._enqueue({ type: 'position', nonce: msg.nonce, trackId: msg.trackId, ...(pos || { bar: null }) });
+              } catch (e) {}
+            }
+          })();
+        } else if (msg.what === 'buffers') {
+          // eslint-disable-next-line @typescript-eslint/no-floating-promises
+          (async () => {
+            if (await this._ensureWasm() && typeof this.wasm.audio_asset_stats === 'function') {
+              try {
+                const stats = JSON.parse(this.wasm.audio_asset_stats());
+                this._enqueue({ type: 'buffers', nonce: msg.nonce, ...stats });
+              } catch (e) {}
+            }
+          })();
+        } else if (msg.what === 'levels') {
+          // eslint-disable-next-line @typescript-eslint/no-floating-promises
+          (async () => {
+            if (await this._ensureWasm() && typeof this.wasm.audio_meter_snapshot === 'function') {
+              try {
+                const snap = JSON.parse(this.wasm.audio_meter_snapshot());
+                this._enqueue({ type: 'levels', nonce: msg.nonce, ...snap });
+              } catch (e) {}
+            }
+          })();
         }
         break;
       }
@@ -419,8 +557,13 @@ class AudioEngineProcessor extends AudioWorkletProcessor {
     }
   }
 
-  _panGains(pan) {
-    // equal-power pan [-1..+1]
+  _panGains(pan, stereo) {
+    // stereo: balance (unity in the centre, only the far side fades)
+    if (stereo) {
+      const away = (p) => Math.cos(clamp(p, 0, 1) * 0.5 * Math.PI);
+      return { l: away(pan), r: away(-pan) };
+    }
+    // mono: equal-power pan [-1..+1]
     const angle = (pan + 1) * 0.25 * Math.PI;
     return { l: Math.cos(angle), r: Math.sin(angle) };
   }
@@ -485,6 +628,12 @@ class AudioEngineProcessor extends AudioWorkletProcessor {
       const panL = t.panL, panR = t.panR;
 
       for (let i = 0; i < length; i++) {
+        if (t.stopping && t.gain.remaining <= 0) {
+          t.playing = false;
+          t.stopping = false;
+          endedNow.push(t.id);
+          break;
+        }
         // Read with linear interpolation
         let idx = Math.floor(pos);
         let frac = pos - idx;
@@ -688,6 +837,11 @@ class AudioEngineProcessor extends AudioWorkletProcessor {
       const t = this.tracks.get(evt.trackId);
       if (!t) return;
       t.playing = true;
+      t.stopping = false;
+      if (t.gainBeforeStop != null) {
+        t.gain.setTarget(t.gainBeforeStop, 0);
+        t.gainBeforeStop = null;
+      }
       const off = evt.offsetSamples >>> 0;
       t.readPos = off * (t.buf.sampleRate / this.sr);
       if (evt.loop) {
@@ -706,6 +860,14 @@ class AudioEngineProcessor extends AudioWorkletProcessor {
     } else if (evt.type === 'stop') {
       const t = this.tracks.get(evt.trackId);
       if (!t) return;
+      const fadeSamples = Math.max(0, Math.floor((evt.fadeOutMs ?? 0) * this.sr / 1000));
+      if (t.playing && fadeSamples > 0) {
+        // ramp to silence; _processSegment ends the track when the ramp completes
+        if (!t.stopping) t.gainBeforeStop = t.gain.target;
+        t.gain.setTarget(0, fadeSamples);
+        t.stopping = true;
+        return;
+      }
       t.playing = false;
       this.pendingNotifies.push({ type: 'trackEnded', trackId: t.id, atSamples: evt.when });
       this._enqueue({ type: 'debug', msg: 'trackStopped', trackId: t.id, at: evt.when })
@@ -732,8 +894,15 @@ class AudioEngineProcessor extends AudioWorkletProcessor {
     if (this.useWasmMixer && this.wasmReady && this.wasm && typeof this.wasm.audio_process_into === 'function') {
       try { this.wasm.audio_process_into(outL, outR) } catch {}
       this.currentSample += frames;
-      // No pending notifications from wasm yet
+      // Engine events come from wasm in this mode; JS-side notifications are stale
       this.pendingNotifies.length = 0;
+      if (typeof this.wasm.audio_drain_events === 'function') {
+        try {
+          const json = this.wasm.audio_drain_events();
+          // Nearly every block has no events; skip the parse
+          if (json !== '[]') for (const e of JSON.parse(json)) this._outbox.push(e);
+        } catch (_) {}
+      }
       return true;
     }
 
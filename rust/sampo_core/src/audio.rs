@@ -1,17 +1,52 @@
-use std::collections::HashMap;
+use std::cell::Cell;
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
 
 #[derive(Default)]
 struct AudioState {
     sr: f32,
-    assets: HashMap<String, AudioAsset>,
-    tracks: HashMap<String, AudioTrack>,
+    current_sample: u64, // output samples rendered since init
+    timeline: VecDeque<Scheduled>,
+    next_seq: u64,
+
+    // Assets are shared with the tracks playing them; tracks and buses live in numeric slots
+    // so the render path never hashes ids or clones PCM.
+    assets: HashMap<String, Rc<AudioAsset>>,
+    retired: Vec<(Rc<AudioAsset>, bool)>, // unloaded or replaced but still playing; true reports the unload when freed
+    memory_budget: usize,                 // bytes of PCM to keep registered, 0 = unlimited
+    use_clock: u64,                       // stamps `AudioAsset::last_used` for LRU eviction
+    tracks: Vec<Option<AudioTrack>>, // freed slots are reused by later tracks
+    track_index: HashMap<String, usize>,
+    buses: Vec<Bus>,
+    bus_index: HashMap<String, usize>,
     duckers: Vec<Ducker>,
+    groups: HashMap<String, MusicGroup>,
+    master: Master,
+    events: Vec<EngineEvent>,
+    meter_window: usize, // samples per metering window, set by `engine_init`
+    interp: Interp, // default for tracks without their own choice
+    sinc: Vec<f32>, // kernel table for `Interp::Sinc`, built by `engine_init`
 }
 
-#[derive(Clone)]
 struct AudioAsset {
+    id: Rc<str>,
     sr: f32,
+    src_sr: f32, // rate the host supplied; differs from `sr` once resampled at registration
     ch: Vec<Vec<f32>>, // [channel][sample]
+    last_used: Cell<u64>,
+}
+
+impl AudioAsset {
+    fn bytes(&self) -> usize {
+        self.ch.iter().map(|c| c.len() * std::mem::size_of::<f32>()).sum()
+    }
+
+    /// Maps a position given by the host in source samples onto this asset's (possibly
+    /// resampled) timeline.
+    fn to_asset_pos(&self, src_pos: usize) -> usize {
+        if self.sr == self.src_sr { return src_pos; }
+        (src_pos as f64 * self.sr as f64 / self.src_sr as f64).round() as usize
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -31,427 +66,3467 @@ impl Default for LoopCfg {
     }
 }
 
-#[derive(Clone)]
+impl LoopCfg {
+    /// Clamps a config built against another asset (the track may have switched since it was
+    /// scheduled) to `asset`: the loop stays inside the PCM and an `xfade` fits twice in it.
+    fn fit(mut self, asset: &AudioAsset) -> LoopCfg {
+        let len = asset.ch[0].len();
+        self.start = self.start.min(len);
+        self.end = self.end.map(|e| e.min(len));
+        self.xfade = self.xfade.min(self.end.unwrap_or(len).saturating_sub(self.start) / 2);
+        self
+    }
+}
+
+/// Notification from the render path to the host, stamped with the engine sample it happened at.
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+#[serde(tag = "type", rename_all = "camelCase", rename_all_fields = "camelCase")]
+pub enum EngineEvent {
+    TrackStarted { track_id: Rc<str>, at_samples: u64 },
+    TrackEnded { track_id: Rc<str>, at_samples: u64 },
+    TrackLooped { track_id: Rc<str>, count: u32, at_samples: u64 },
+    TransitionCompleted { track_id: Rc<str>, asset_id: Rc<str>, at_samples: u64 },
+    MarkerReached { track_id: Rc<str>, marker: usize, at_samples: u64 },
+    /// An asset's PCM was freed: unloaded (at once or, if deferred, when its last track let go)
+    /// or evicted to stay within the memory budget.
+    AssetUnloaded { asset_id: Rc<str>, at_samples: u64 },
+    /// A play request hit the bus voice limit. `dropped_track_id` is the voice that was stolen
+    /// or refused; it is absent when the request was queued instead.
+    PolyphonyLimited {
+        bus_id: Rc<str>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        dropped_track_id: Option<Rc<str>>,
+        at_samples: u64,
+    },
+}
+
+/// Events beyond this many undrained ones are dropped so a host that never drains cannot
+/// grow the queue without bound. The queue is preallocated to this size, so pushing from the
+/// render path never allocates.
+const MAX_PENDING_EVENTS: usize = 1024;
+
+fn push_event(events: &mut Vec<EngineEvent>, ev: EngineEvent) {
+    if events.len() < MAX_PENDING_EVENTS { events.push(ev); }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum TransitionAt { Now, LoopEnd, NextMarker, NextBeat, NextBar, Bar(u32) }
+
+impl TransitionAt {
+    /// `now`, `loopEnd`, `nextMarker`, `nextBeat`, `nextBar` or `bar:N` (bars count from 1).
+    fn parse(s: &str) -> Option<TransitionAt> {
+        Some(match s {
+            "now" => TransitionAt::Now,
+            "loopEnd" => TransitionAt::LoopEnd,
+            "nextMarker" => TransitionAt::NextMarker,
+            "nextBeat" => TransitionAt::NextBeat,
+            "nextBar" => TransitionAt::NextBar,
+            _ => TransitionAt::Bar(s.strip_prefix("bar:")?.trim().parse().ok().filter(|&n| n >= 1)?),
+        })
+    }
+}
+
+/// Where a crossfaded switch sits relative to its boundary: ending on it, or centred on it.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum FadeAlign { Before, Center }
+
+/// Equal-power crossfade between the outgoing and incoming asset of a transition.
+#[derive(Clone, Copy)]
+struct SwitchFade {
+    len: usize, // engine samples
+    align: FadeAlign,
+}
+
+fn switch_fade(fade_ms: f32, align: &str, sr: f32) -> Option<Option<SwitchFade>> {
+    let align = match align {
+        "before" | "" => FadeAlign::Before,
+        "center" => FadeAlign::Center,
+        _ => return None,
+    };
+    let len = ms_to_samples(fade_ms, sr);
+    Some((len > 0).then_some(SwitchFade { len, align }))
+}
+
+/// The outgoing asset of a crossfaded switch, still playing out under the incoming one.
+struct FadeTail {
+    asset: Rc<AudioAsset>,
+    pos: f64,
+    step: f64,
+    loop_cfg: LoopCfg,
+    done: usize,
+    len: usize,
+}
+
+impl FadeTail {
+    /// Next frame of the outgoing asset; it keeps looping, so a fade centred on a loop end
+    /// carries on into the loop start.
+    fn next(&mut self, interp: Interp, sinc: &[f32]) -> (f32, f32) {
+        let len = self.asset.ch[0].len();
+        let lend = self.loop_cfg.end.unwrap_or(len).min(len);
+        if self.loop_cfg.mode != LoopMode::None && lend > self.loop_cfg.start && self.pos >= lend as f64 {
+            self.pos -= (lend - self.loop_cfg.start) as f64;
+        }
+        let idx = self.pos.floor() as isize;
+        let frac = self.pos - idx as f64;
+        let asset = &self.asset;
+        let out = interpolate(interp, sinc, frac, |k| frame_at(asset, idx + k));
+        self.pos += self.step;
+        self.done += 1;
+        out
+    }
+}
+
+/// Musical grid of a track, in the host's source samples like markers and loop points.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct TempoMap {
+    bpm: f64, // beats per minute, counting the time signature's beat unit
+    beats_per_bar: u32,
+    beat_unit: u32,
+    downbeat: f64, // source sample where bar 1 starts
+}
+
+impl TempoMap {
+    /// Beat length and first downbeat on `asset`'s (possibly resampled) timeline.
+    fn grid(&self, asset: &AudioAsset) -> (f64, f64) {
+        let scale = asset.sr as f64 / asset.src_sr as f64;
+        (asset.src_sr as f64 * 60.0 / self.bpm * scale, self.downbeat * scale)
+    }
+}
+
+/// Engine command, applied by the timeline at an exact output sample.
+enum Cmd {
+    Play { track: String, offset: u32, loop_cfg: LoopCfg },
+    Stop { track: String, fade: usize },
+    SetLoop { track: String, loop_cfg: LoopCfg },
+    Transition { track: String, at: TransitionAt, asset: Rc<AudioAsset>, loop_cfg: LoopCfg, fade: Option<SwitchFade> },
+    TrackGain { track: String, gain: f32, ramp: usize },
+    TrackPan { track: String, pan: f32, ramp: usize },
+    BusGain { bus: String, gain: f32, ramp: usize },
+    GroupPlay { tracks: Vec<String>, offset: u32, loop_cfg: LoopCfg },
+    GroupStop { tracks: Vec<String>, fade: usize },
+    GroupTransition { tracks: Vec<(String, Rc<AudioAsset>)>, at: TransitionAt, loop_cfg: LoopCfg, fade: Option<SwitchFade> },
+    StemGains { gains: Vec<(String, f32)>, ramp: usize },
+}
+
+/// Timeline entry; ties on `when` keep submission order through `seq`.
+struct Scheduled {
+    when: u64,
+    seq: u64,
+    cmd: Cmd,
+}
+
 struct AudioTrack {
-    id: String,
-    bus: String,
-    asset_id: String,
+    id: Rc<str>,
+    bus: usize,
+    asset: Rc<AudioAsset>,
     pos: f64,
     step: f64,
-    gain: f32,
+    gain: Ramp,
+    pan: Ramp,
     pan_l: f32,
     pan_r: f32,
     playing: bool,
     loop_cfg: LoopCfg,
-    markers: Vec<usize>,
-    pending_switch: Option<(String, LoopCfg)>,
+    markers: Vec<(usize, usize)>, // (position on the asset timeline, value as given by the host)
+    tempo: Option<TempoMap>,
+    marker_cursor: usize, // index of the next marker not yet reported
+    loop_count: u32,
+    pending_switch: Option<(Rc<AudioAsset>, LoopCfg)>,
     pending_switch_at: Option<usize>,
+    pending_fade: Option<SwitchFade>, // crossfade for the armed switch; None cuts
+    tail: Option<FadeTail>,
+    fade: Ramp,
+    stopping: bool,
+    lpf: Lpf,
+    interp: Option<Interp>, // None follows the engine default
+    started_at: u64,        // engine sample of the last start, to find the oldest voice
+    rendered_at: u64,       // block already mixed (a queued voice can start mid-block)
+}
+
+/// Stems that play as one. Play, stop, transitions and stem levels are expanded into a single
+/// timeline command for all members, and the members share sample rate, length and markers,
+/// so their playheads never drift apart.
+struct MusicGroup {
+    members: Vec<String>, // track ids; the first one leads when a transition point is resolved
+}
+
+/// Mixer bus: tracks are summed into `acc_l`/`acc_r`, then duckers, bus gain and the
+/// mute/solo gate are applied before the master sum.
+struct Bus {
+    id: Rc<str>,
+    gain: Ramp,
+    gate: Ramp, // smoothed mute/solo gate (0 or 1)
+    mute: bool,
+    solo: bool,
+    lpf: Lpf,
+    acc_l: Vec<f32>,
+    acc_r: Vec<f32>,
+    max_voices: usize, // 0 = unlimited
+    policy: VoicePolicy,
+    queue: VecDeque<QueuedPlay>,
+    meter: Meter,
+}
+
+impl Bus {
+    fn new(id: &str, gain_db: f32) -> Self {
+        Bus {
+            id: id.into(),
+            gain: Ramp::new(db_to_lin(gain_db)),
+            gate: Ramp::new(1.0),
+            mute: false,
+            solo: false,
+            lpf: Lpf::default(),
+            acc_l: vec![0.0; MAX_BLOCK],
+            acc_r: vec![0.0; MAX_BLOCK],
+            max_voices: 0,
+            policy: VoicePolicy::DropOldest,
+            queue: VecDeque::with_capacity(MAX_QUEUED_VOICES),
+            meter: Meter::default(),
+        }
+    }
+}
+
+/// What a bus at its voice limit does with another play request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum VoicePolicy {
+    DropOldest, // steal the longest-playing voice with a short fade
+    DropNewest, // refuse the new voice and remove its track
+    Queue,      // start the new voice when one ends
+}
+
+/// Play request waiting for a free voice on a `queue` bus.
+struct QueuedPlay {
+    track: Rc<str>,
+    offset: u32,
+    loop_cfg: LoopCfg,
+}
+
+/// Queued plays per bus; the queue is preallocated to this size so the render path never
+/// grows it, and requests beyond it are refused like `dropNewest`.
+const MAX_QUEUED_VOICES: usize = 32;
+const STEAL_FADE_MS: f32 = 10.0;
+
+/// Longest stretch rendered in one pass; bus scratch is allocated once at this size and longer
+/// host blocks are rendered in several passes.
+const MAX_BLOCK: usize = 1024;
+
+const BUS_GATE_MS: f32 = 5.0;
+
+/// Final stage: headroom and master gain, then the look-ahead limiter.
+struct Master {
+    headroom: f32,
+    gain: Ramp,
+    limiter: Limiter,
+    meter: Meter,
+    true_peak: TruePeak,
+}
+
+impl Default for Master {
+    fn default() -> Self {
+        Master { headroom: db_to_lin(-6.0), gain: Ramp::new(1.0), limiter: Limiter::default(), meter: Meter::default(), true_peak: TruePeak::default() }
+    }
+}
+
+/// Metering window; readings are published each time a window completes.
+const METER_WINDOW_MS: f32 = 300.0;
+
+/// Windowed level meter: RMS (mean of both channels' power) and sample peak.
+#[derive(Default)]
+struct Meter {
+    n: usize,
+    sum_sq: f64,
+    peak: f32,
+    rms_out: f32,
+    peak_out: f32,
+}
+
+impl Meter {
+    fn add(&mut self, l: f32, r: f32, window: usize) {
+        self.sum_sq += 0.5 * (l as f64 * l as f64 + r as f64 * r as f64);
+        self.peak = self.peak.max(l.abs()).max(r.abs());
+        self.n += 1;
+        if self.n >= window.max(1) {
+            self.rms_out = (self.sum_sq / self.n as f64).sqrt() as f32;
+            self.peak_out = self.peak;
+            self.n = 0;
+            self.sum_sq = 0.0;
+            self.peak = 0.0;
+        }
+    }
+
+    fn reading(&self) -> LevelReading {
+        LevelReading { rms_db: lin_to_db(self.rms_out), peak_db: lin_to_db(self.peak_out) }
+    }
+}
+
+const TP_PHASES: usize = 4;
+const TP_TAPS: usize = 12;
+
+/// 4x oversampled peak detector in the spirit of ITU-R BS.1770: a polyphase windowed-sinc
+/// interpolator estimates the inter-sample peaks that a sample peak meter misses. Readings
+/// are delayed by half the kernel and published on the same windows as [`Meter`].
+struct TruePeak {
+    coeffs: [[f32; TP_TAPS]; TP_PHASES],
+    hist: [[f32; TP_TAPS]; 2],
+    pos: usize,
+    n: usize,
+    peak: f32,
+    peak_out: f32,
+}
+
+impl Default for TruePeak {
+    fn default() -> Self {
+        use std::f64::consts::PI;
+        let mut coeffs = [[0.0f32; TP_TAPS]; TP_PHASES];
+        let half = (TP_TAPS / 2) as f64;
+        for (p, row) in coeffs.iter_mut().enumerate() {
+            let taps: Vec<f64> = (0..TP_TAPS)
+                .map(|k| {
+                    let d = k as f64 - (half - 1.0) - p as f64 / TP_PHASES as f64;
+                    let u = d / half;
+                    let w = 0.42 + 0.5 * (PI * u).cos() + 0.08 * (2.0 * PI * u).cos();
+                    let sinc = if d.abs() < 1e-12 { 1.0 } else { (PI * d).sin() / (PI * d) };
+                    sinc * w
+                })
+                .collect();
+            let sum: f64 = taps.iter().sum();
+            for (c, t) in row.iter_mut().zip(&taps) { *c = (t / sum) as f32; }
+        }
+        TruePeak { coeffs, hist: [[0.0; TP_TAPS]; 2], pos: 0, n: 0, peak: 0.0, peak_out: 0.0 }
+    }
+}
+
+impl TruePeak {
+    fn add(&mut self, l: f32, r: f32, window: usize) {
+        self.hist[0][self.pos] = l;
+        self.hist[1][self.pos] = r;
+        self.pos = (self.pos + 1) % TP_TAPS;
+        for ch in &self.hist {
+            for row in &self.coeffs {
+                let mut acc = 0.0f32;
+                for (k, c) in row.iter().enumerate() { acc += ch[(self.pos + k) % TP_TAPS] * c; }
+                self.peak = self.peak.max(acc.abs());
+            }
+        }
+        self.n += 1;
+        if self.n >= window.max(1) {
+            self.peak_out = self.peak;
+            self.n = 0;
+            self.peak = 0.0;
+        }
+    }
+}
+
+/// Level of one meter point in dBFS (silence reads -120).
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LevelReading {
+    pub rms_db: f32,
+    pub peak_db: f32,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BusLevels {
+    pub bus_id: String,
+    #[serde(flatten)]
+    pub level: LevelReading,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DuckerLevels {
+    pub target_bus_id: String,
+    pub key_bus_ids: Vec<String>,
+    pub gain_reduction_db: f32,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MasterLevels {
+    #[serde(flatten)]
+    pub level: LevelReading,
+    pub true_peak_db: f32,
+}
+
+/// Metering snapshot for the host's debug HUD: last completed window per bus (post fader,
+/// gate and low-pass) and for the master output (post limiter), plus live ducker reduction.
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MeterSnapshot {
+    pub at_samples: u64,
+    pub master: MasterLevels,
+    pub buses: Vec<BusLevels>,
+    pub duckers: Vec<DuckerLevels>,
+}
+
+fn lin_to_db(x: f32) -> f32 { 20.0 * x.max(1e-6).log10() }
+
+/// Running limiter and clip statistics, queried by the host for its debug HUD.
+#[derive(Clone, Copy, Default, Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LimiterStats {
+    /// Output samples (per channel) whose magnitude exceeded 0 dBFS.
+    pub clip_count: u64,
+    /// Samples rendered with any gain reduction applied.
+    pub limited_samples: u64,
+    pub gain_reduction_db: f32,
+    pub max_gain_reduction_db: f32,
+}
+
+/// Soft-knee look-ahead peak limiter. The minimum required gain over the look-ahead window is
+/// averaged over the last `attack` samples (at most the look-ahead), so the gain ramps down
+/// linearly and reaches a peak's gain exactly as the peak reaches the output; every output
+/// sample still gets at most its own required gain. Recovery follows the release time.
+struct Limiter {
+    enabled: bool,
+    ceiling_db: f32,
+    knee_db: f32,
+    lookahead: usize,
+    attack_ms: f32,
+    release_ms: f32,
+    attack: usize, // ramp length in samples, 1..=lookahead + 1
+    release: f32,
+    ramp: Vec<f32>, // last `attack` window minima
+    ramp_pos: usize,
+    ramp_sum: f64,
+    delay: Vec<[f32; 2]>,
+    delay_pos: usize,
+    win: Vec<(u64, f32)>, // monotonic ring of (sample, required gain)
+    win_head: usize,
+    win_len: usize,
+    t: u64,
+    env: f32,
+    stats: LimiterStats,
+}
+
+impl Default for Limiter {
+    fn default() -> Self {
+        Limiter {
+            enabled: true,
+            ceiling_db: -1.0,
+            knee_db: 3.0,
+            lookahead: 256,
+            attack_ms: 2.0,
+            release_ms: 100.0,
+            attack: 1,
+            release: 1.0,
+            ramp: Vec::new(),
+            ramp_pos: 0,
+            ramp_sum: 0.0,
+            delay: Vec::new(),
+            delay_pos: 0,
+            win: Vec::new(),
+            win_head: 0,
+            win_len: 0,
+            t: 0,
+            env: 1.0,
+            stats: LimiterStats::default(),
+        }
+    }
+}
+
+impl Limiter {
+    fn configure(&mut self, sr: f32) {
+        self.attack = ms_to_samples(self.attack_ms, sr).clamp(1, self.lookahead + 1);
+        self.ramp = vec![1.0; self.attack];
+        self.ramp_pos = 0;
+        self.ramp_sum = self.attack as f64;
+        self.release = 1.0 - (-1.0f32 / (sr * self.release_ms.max(0.01) / 1000.0)).exp();
+        self.delay = vec![[0.0; 2]; self.lookahead];
+        self.delay_pos = 0;
+        self.win = vec![(0, 1.0); self.lookahead + 1];
+        self.win_head = 0;
+        self.win_len = 0;
+        self.env = 1.0;
+    }
+
+    /// Gain needed to bring `peak` under the ceiling, with a quadratic soft knee.
+    fn required_gain(&self, peak: f32) -> f32 {
+        if peak <= 0.0 { return 1.0; }
+        let over = 20.0 * peak.log10() - self.ceiling_db;
+        let half = self.knee_db * 0.5;
+        let red_db = if over <= -half {
+            return 1.0;
+        } else if over >= half || self.knee_db <= 0.0 {
+            over
+        } else {
+            (over + half) * (over + half) / (2.0 * self.knee_db)
+        };
+        db_to_lin(-red_db)
+    }
+
+    fn process(&mut self, l: f32, r: f32) -> (f32, f32) {
+        let (mut ol, mut or) = (l, r);
+        if self.enabled {
+            let gd = self.required_gain(l.abs().max(r.abs()));
+            let cap = self.win.len();
+            while self.win_len > 0 && self.win[(self.win_head + self.win_len - 1) % cap].1 >= gd { self.win_len -= 1; }
+            self.win[(self.win_head + self.win_len) % cap] = (self.t, gd);
+            self.win_len += 1;
+            while self.win[self.win_head].0 + (self.lookahead as u64) < self.t {
+                self.win_head = (self.win_head + 1) % cap;
+                self.win_len -= 1;
+            }
+            let wmin = self.win[self.win_head].1;
+            // Each averaged minimum covers the sample leaving the delay line now
+            self.ramp_sum += (wmin - self.ramp[self.ramp_pos]) as f64;
+            self.ramp[self.ramp_pos] = wmin;
+            self.ramp_pos = (self.ramp_pos + 1) % self.attack;
+            let target = ((self.ramp_sum / self.attack as f64) as f32).min(1.0);
+            if target < self.env {
+                self.env = target;
+            } else {
+                self.env += self.release * (target - self.env);
+            }
+            let g = self.env;
+            if self.lookahead > 0 {
+                let d = self.delay[self.delay_pos];
+                self.delay[self.delay_pos] = [l, r];
+                self.delay_pos = (self.delay_pos + 1) % self.lookahead;
+                ol = d[0] * g;
+                or = d[1] * g;
+            } else {
+                ol *= g;
+                or *= g;
+            }
+            self.t += 1;
+            let gr_db = -20.0 * g.max(1e-9).log10();
+            self.stats.gain_reduction_db = gr_db;
+            if gr_db > self.stats.max_gain_reduction_db { self.stats.max_gain_reduction_db = gr_db; }
+            if g < 1.0 { self.stats.limited_samples += 1; }
+        }
+        if ol.abs() > 1.0 { self.stats.clip_count += 1; }
+        if or.abs() > 1.0 { self.stats.clip_count += 1; }
+        (ol, or)
+    }
 }
 
 thread_local! {
     static AUDIO: std::cell::RefCell<AudioState> = std::cell::RefCell::new(AudioState::default());
 }
 
-fn pan_coeffs(pan: f32) -> (f32, f32) {
+impl AudioState {
+    fn track(&self, id: &str) -> Option<&AudioTrack> {
+        self.tracks.get(*self.track_index.get(id)?)?.as_ref()
+    }
+
+    fn track_mut(&mut self, id: &str) -> Option<&mut AudioTrack> {
+        self.tracks.get_mut(*self.track_index.get(id)?)?.as_mut()
+    }
+
+    fn insert_track(&mut self, t: AudioTrack) {
+        if let Some(&slot) = self.track_index.get(&*t.id) {
+            self.tracks[slot] = Some(t);
+            return;
+        }
+        let slot = match self.tracks.iter().position(|s| s.is_none()) {
+            Some(i) => i,
+            None => { self.tracks.push(None); self.tracks.len() - 1 }
+        };
+        self.track_index.insert(t.id.to_string(), slot);
+        self.tracks[slot] = Some(t);
+    }
+
+    fn remove_track(&mut self, id: &str) {
+        if let Some(slot) = self.track_index.remove(id) { self.tracks[slot] = None; }
+    }
+
+    /// Marks `asset` as the most recently used one.
+    fn touch(&mut self, asset: &AudioAsset) {
+        self.use_clock += 1;
+        asset.last_used.set(self.use_clock);
+    }
+
+    fn asset_bytes(&self) -> usize {
+        self.assets.values().chain(self.retired.iter().map(|(x, _)| x)).map(|x| x.bytes()).sum()
+    }
+
+    /// A one-shot that played to its end with nothing set to start it again: no play scheduled
+    /// or queued. (Stopped tracks are removed when their fade ends.)
+    fn is_idle(&self, t: &AudioTrack) -> bool {
+        !t.playing
+            && t.pos as usize + 1 >= t.asset.ch[0].len()
+            && !self.timeline.iter().any(|e| match &e.cmd {
+                Cmd::Play { track, .. } => *track == *t.id,
+                Cmd::GroupPlay { tracks, .. } => tracks.iter().any(|x| *x == *t.id),
+                _ => false,
+            })
+            && !self.buses[t.bus].queue.iter().any(|q| q.track == t.id)
+    }
+
+    /// Whether `asset` (as held by the registry or a retired entry) is referenced by a playing
+    /// or scheduled track, an armed transition or a timeline command. Idle tracks don't count:
+    /// they let go of the asset when it is freed.
+    fn asset_in_use(&self, asset: &Rc<AudioAsset>) -> bool {
+        let idle = self.tracks.iter().flatten().filter(|t| Rc::ptr_eq(&t.asset, asset) && self.is_idle(t)).count();
+        Rc::strong_count(asset) > 1 + idle
+    }
+
+    /// Removes the idle tracks still holding `asset`, so dropping it frees the PCM.
+    fn drop_idle_holders(&mut self, asset: &Rc<AudioAsset>) {
+        for slot in 0..self.tracks.len() {
+            if !matches!(&self.tracks[slot], Some(t) if Rc::ptr_eq(&t.asset, asset) && self.is_idle(t)) { continue; }
+            if let Some(t) = self.tracks[slot].take() { self.track_index.remove(&*t.id); }
+        }
+    }
+
+    /// Evicts unused assets, least recently used first, until the budget holds. `keep` is
+    /// spared (the asset being registered).
+    fn enforce_budget(&mut self, keep: Option<&str>) {
+        if self.memory_budget == 0 { return; }
+        let mut total = self.asset_bytes();
+        while total > self.memory_budget {
+            let victim = self.assets.iter()
+                .filter(|(id, x)| Some(id.as_str()) != keep && !self.asset_in_use(x))
+                .min_by_key(|(_, x)| x.last_used.get())
+                .map(|(id, _)| id.clone());
+            let asset = match victim.and_then(|id| self.assets.remove(&id)) { Some(x) => x, None => break };
+            self.drop_idle_holders(&asset);
+            total -= asset.bytes();
+            let at_samples = self.current_sample;
+            push_event(&mut self.events, EngineEvent::AssetUnloaded { asset_id: asset.id.clone(), at_samples });
+        }
+    }
+
+    /// Frees retired assets no track uses any more.
+    fn release_retired(&mut self) {
+        let mut i = 0;
+        while i < self.retired.len() {
+            if self.asset_in_use(&self.retired[i].0) {
+                i += 1;
+                continue;
+            }
+            let (asset, notify) = self.retired.swap_remove(i);
+            self.drop_idle_holders(&asset);
+            if notify {
+                let at_samples = self.current_sample;
+                push_event(&mut self.events, EngineEvent::AssetUnloaded { asset_id: asset.id.clone(), at_samples });
+            }
+        }
+    }
+
+    fn bus_mut(&mut self, id: &str) -> Option<&mut Bus> {
+        self.buses.get_mut(*self.bus_index.get(id)?)
+    }
+
+    /// Slot of bus `id`, creating a unity-gain bus if it was never created.
+    fn bus_slot(&mut self, id: &str) -> usize {
+        if let Some(&slot) = self.bus_index.get(id) { return slot; }
+        self.buses.push(Bus::new(id, 0.0));
+        self.bus_index.insert(id.to_string(), self.buses.len() - 1);
+        self.buses.len() - 1
+    }
+}
+
+/// Linear per-sample ramp towards a target value.
+#[derive(Clone, Copy)]
+struct Ramp {
+    value: f32,
+    target: f32,
+    remaining: usize,
+    delta: f32,
+}
+
+impl Ramp {
+    fn new(value: f32) -> Self { Ramp { value, target: value, remaining: 0, delta: 0.0 } }
+
+    fn set_target(&mut self, target: f32, samples: usize) {
+        self.target = target;
+        if samples == 0 {
+            self.value = target;
+            self.remaining = 0;
+            self.delta = 0.0;
+        } else {
+            self.remaining = samples;
+            self.delta = (target - self.value) / samples as f32;
+        }
+    }
+
+    fn step(&mut self) -> f32 {
+        if self.remaining > 0 {
+            self.value += self.delta;
+            self.remaining -= 1;
+            if self.remaining == 0 { self.value = self.target; }
+        }
+        self.value
+    }
+
+    fn is_ramping(&self) -> bool { self.remaining > 0 }
+}
+
+fn ms_to_samples(ms: f32, sr: f32) -> usize { (ms.max(0.0) * sr / 1000.0) as usize }
+
+fn db_to_lin(db: f32) -> f32 { 10.0f32.powf(db / 20.0) }
+
+/// RBJ cookbook biquad, transposed direct form II.
+#[derive(Clone, Copy)]
+struct Biquad { b0: f32, b1: f32, b2: f32, a1: f32, a2: f32 }
+
+impl Biquad {
+    fn lowpass(cutoff_hz: f32, q: f32, sr: f32) -> Self {
+        let f = cutoff_hz.clamp(10.0, sr * 0.49);
+        let w0 = 2.0 * std::f32::consts::PI * f / sr;
+        let (sn, cs) = w0.sin_cos();
+        let alpha = sn / (2.0 * q.max(0.1));
+        let a0 = 1.0 + alpha;
+        let b1 = (1.0 - cs) / a0;
+        Biquad { b0: b1 * 0.5, b1, b2: b1 * 0.5, a1: -2.0 * cs / a0, a2: (1.0 - alpha) / a0 }
+    }
+
+    fn run(&self, z: &mut [f32; 2], x: f32) -> f32 {
+        let y = self.b0 * x + z[0];
+        z[0] = self.b1 * x - self.a1 * y + z[1];
+        z[1] = self.b2 * x - self.a2 * y;
+        y
+    }
+}
+
+/// Stereo low-pass of one (12 dB/oct) or two (24 dB/oct) biquad stages. Cutoff ramps in the
+/// log-frequency domain; coefficients are recomputed per sample only while a ramp is running.
+#[derive(Clone)]
+struct Lpf {
+    enabled: bool,
+    stages: usize,
+    log_cutoff: Ramp,
+    q: Ramp,
+    coeffs: Biquad,
+    z: [[[f32; 2]; 2]; 2], // [stage][channel]
+}
+
+impl Default for Lpf {
+    fn default() -> Self {
+        Lpf {
+            enabled: false,
+            stages: 1,
+            log_cutoff: Ramp::new(1000.0f32.ln()),
+            q: Ramp::new(std::f32::consts::FRAC_1_SQRT_2),
+            coeffs: Biquad { b0: 1.0, b1: 0.0, b2: 0.0, a1: 0.0, a2: 0.0 },
+            z: [[[0.0; 2]; 2]; 2],
+        }
+    }
+}
+
+impl Lpf {
+    /// `cutoff_hz <= 0` disables the filter. Enabling a disabled filter with a ramp sweeps
+    /// down from just below Nyquist so the change stays click-free.
+    fn set(&mut self, cutoff_hz: f32, q: f32, ramp: usize, order: u8, sr: f32) {
+        if cutoff_hz <= 0.0 || !cutoff_hz.is_finite() {
+            self.enabled = false;
+            self.z = [[[0.0; 2]; 2]; 2];
+            return;
+        }
+        if !self.enabled {
+            let from = if ramp > 0 { sr * 0.45 } else { cutoff_hz };
+            self.log_cutoff = Ramp::new(from.ln());
+            self.z = [[[0.0; 2]; 2]; 2];
+        }
+        self.enabled = true;
+        self.stages = if order >= 2 { 2 } else { 1 };
+        self.log_cutoff.set_target(cutoff_hz.ln(), ramp);
+        self.q.set_target(q.max(0.1), ramp);
+        self.coeffs = Biquad::lowpass(self.log_cutoff.value.exp(), self.q.value, sr);
+    }
+
+    fn process(&mut self, l: f32, r: f32, sr: f32) -> (f32, f32) {
+        if !self.enabled { return (l, r); }
+        if self.log_cutoff.is_ramping() || self.q.is_ramping() {
+            let f = self.log_cutoff.step().exp();
+            let q = self.q.step();
+            self.coeffs = Biquad::lowpass(f, q, sr);
+        }
+        let (mut l, mut r) = (l, r);
+        for s in 0..self.stages {
+            l = self.coeffs.run(&mut self.z[s][0], l);
+            r = self.coeffs.run(&mut self.z[s][1], r);
+        }
+        (l, r)
+    }
+}
+
+/// Output gains for `pan` in `-1..=1`. Mono sources use the equal-power pan law; stereo sources
+/// get a balance control that keeps both sides at unity in the centre and attenuates only the
+/// side being panned away from.
+fn pan_coeffs(pan: f32, stereo: bool) -> (f32, f32) {
+    if stereo {
+        let away = |p: f32| (p.clamp(0.0, 1.0) * std::f32::consts::FRAC_PI_2).cos();
+        return (away(pan), away(-pan));
+    }
     let angle = (pan + 1.0) * 0.25 * std::f32::consts::PI;
     (angle.cos(), angle.sin())
 }
 
-fn loop_cfg(mode: &str, start: u32, end: i32, xfade_ms: u32, sr: f32) -> LoopCfg {
-    let end_opt = if end >= 0 { Some(end as usize) } else { None };
+/// Builds a loop configuration against `asset`. `start`/`end` are in the host's source samples
+/// and are mapped onto the asset timeline, so loops stay put when the asset was resampled at
+/// registration. Returns `None` when an `xfade` loop is too short to hold both the faded-out
+/// tail and the faded-in head (`loop length < 2 * xfade`).
+fn loop_cfg(mode: &str, start: u32, end: i32, xfade_ms: u32, asset: &AudioAsset) -> Option<LoopCfg> {
+    let (sr, len) = (asset.sr, asset.ch[0].len());
+    let start = asset.to_asset_pos(start as usize);
+    let end_opt = if end >= 0 { Some(asset.to_asset_pos(end as usize)) } else { None };
     let m = match mode {
         "seamless" => LoopMode::Seamless,
         "xfade" => LoopMode::Xfade,
         _ => LoopMode::None,
     };
     let xfade = if m == LoopMode::Xfade { ((xfade_ms as f32) * sr / 1000.0).max(0.0) as usize } else { 0 };
-    LoopCfg { mode: m, start: start as usize, end: end_opt, xfade }
+    if m == LoopMode::Xfade {
+        let lend = end_opt.unwrap_or(len).min(len);
+        let loop_len = lend.saturating_sub(start);
+        if loop_len < xfade.saturating_mul(2) { return None; }
+    }
+    Some(LoopCfg { mode: m, start, end: end_opt, xfade })
+}
+
+/// Equal-power crossfade gains `(fade_out, fade_in)` for `t` in `[0, 1]`.
+fn equal_power(t: f64) -> (f32, f32) {
+    let a = t.clamp(0.0, 1.0) * std::f64::consts::FRAC_PI_2;
+    (a.cos() as f32, a.sin() as f32)
 }
 
 #[derive(Clone)]
 struct Ducker {
-    target_bus: String,
-    key_bus: String,
+    target_bus: usize,
+    keys: Vec<KeyDetector>,
+    combine: KeyCombine,
     threshold_db: f32,
     threshold_lin: f32,
     ratio: f32,
     attack: f32,
     release: f32,
+    hold: usize,      // samples the reduction is held after the key drops below threshold
+    hold_left: usize,
     max_atten_db: f32,
     makeup_lin: f32,
     env: f32,
     gr: f32,
 }
 
+/// How the levels of several key buses drive one ducker.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum KeyCombine { Max, EnergySum }
+
+/// Sidechain detector for one key bus: instantaneous peak, or RMS over a moving window.
+#[derive(Clone)]
+struct KeyDetector {
+    bus: usize,
+    ring: Vec<f32>, // power history of the RMS window, preallocated; empty for peak detection
+    pos: usize,
+    sum: f64,
+}
+
+impl KeyDetector {
+    /// Level for the next sample of mean channel power `p`.
+    fn level(&mut self, p: f32) -> f32 {
+        if self.ring.is_empty() { return p.sqrt(); }
+        self.sum += (p - self.ring[self.pos]) as f64;
+        self.ring[self.pos] = p;
+        self.pos = (self.pos + 1) % self.ring.len();
+        (self.sum.max(0.0) / self.ring.len() as f64).sqrt() as f32
+    }
+}
+
 pub fn engine_init(sample_rate: f32) {
     AUDIO.with(|a| {
         let mut st = a.borrow_mut();
         st.sr = if sample_rate > 0.0 { sample_rate } else { 48000.0 };
+        let sr = st.sr;
+        st.master.limiter.configure(sr);
+        st.meter_window = ms_to_samples(METER_WINDOW_MS, sr);
+        if st.sinc.is_empty() { st.sinc = sinc_table(); }
+        let pending = st.events.len();
+        st.events.reserve(MAX_PENDING_EVENTS.saturating_sub(pending));
     });
 }
 
-pub fn engine_register_asset(id: &str, sample_rate: f32, channels: Vec<Vec<f32>>) -> bool {
-    let ch_vec: Vec<Vec<f32>> = channels;
-    if ch_vec.is_empty() { return false; }
-    AUDIO.with(|a| {
-        let mut st = a.borrow_mut();
-        st.assets.insert(id.to_string(), AudioAsset { sr: sample_rate, ch: ch_vec });
-    });
-    true
+/// Master pre-limiter headroom (default -6 dB).
+pub fn engine_set_headroom(headroom_db: f32) {
+    AUDIO.with(|a| a.borrow_mut().master.headroom = db_to_lin(headroom_db))
 }
 
-pub fn engine_create_track(track_id: &str, asset_id: &str, pan: f32, gain_db: f32) -> bool {
+pub fn engine_set_master_gain(gain_db: f32, ramp_ms: f32) {
     AUDIO.with(|a| {
         let mut st = a.borrow_mut();
-        let asset = match st.assets.get(asset_id) { Some(x) => x.clone(), None => return false };
-        let (pl, pr) = pan_coeffs(pan.clamp(-1.0, 1.0));
-        let step = (asset.sr / st.sr) as f64;
-        let t = AudioTrack {
-            id: track_id.to_string(),
-            bus: "sfx".to_string(),
-            asset_id: asset_id.to_string(),
-            pos: 0.0,
-            step,
-            gain: 10.0f32.powf(gain_db / 20.0),
-            pan_l: pl,
-            pan_r: pr,
-            playing: false,
-            loop_cfg: LoopCfg::default(),
-            markers: Vec::new(),
-            pending_switch: None,
-            pending_switch_at: None,
-        };
-        st.tracks.insert(track_id.to_string(), t);
-        true
+        let ramp = ms_to_samples(ramp_ms, st.sr);
+        st.master.gain.set_target(db_to_lin(gain_db), ramp);
     })
 }
 
-pub fn engine_create_track_bus(track_id: &str, bus: &str, asset_id: &str, pan: f32, gain_db: f32) -> bool {
+/// Configures the master limiter (defaults: on, -1 dBFS ceiling, 256 samples look-ahead, 2 ms
+/// attack, 100 ms release, 3 dB knee). The attack is the length of the linear gain ramp, capped
+/// at the look-ahead. Reconfiguring clears the look-ahead buffer.
+pub fn engine_set_limiter(enabled: bool, ceiling_db: f32, lookahead_samples: u32, attack_ms: f32, release_ms: f32, knee_db: f32) {
     AUDIO.with(|a| {
         let mut st = a.borrow_mut();
-        let asset = match st.assets.get(asset_id) { Some(x) => x.clone(), None => return false };
-        let (pl, pr) = pan_coeffs(pan.clamp(-1.0, 1.0));
-        let step = (asset.sr / st.sr) as f64;
-        let t = AudioTrack {
-            id: track_id.to_string(),
-            bus: bus.to_string(),
-            asset_id: asset_id.to_string(),
-            pos: 0.0,
-            step,
-            gain: 10.0f32.powf(gain_db / 20.0),
-            pan_l: pl,
-            pan_r: pr,
-            playing: false,
-            loop_cfg: LoopCfg::default(),
-            markers: Vec::new(),
-            pending_switch: None,
-            pending_switch_at: None,
-        };
-        st.tracks.insert(track_id.to_string(), t);
-        true
+        let sr = st.sr;
+        let lim = &mut st.master.limiter;
+        lim.enabled = enabled;
+        lim.ceiling_db = ceiling_db.min(0.0);
+        lim.lookahead = lookahead_samples as usize;
+        lim.attack_ms = attack_ms;
+        lim.release_ms = release_ms;
+        lim.knee_db = knee_db.max(0.0);
+        lim.configure(sr);
     })
 }
 
-pub fn engine_schedule_play(track_id: &str, offset_samples: u32, loop_mode: &str, loop_start: u32, loop_end: i32, xfade_ms: u32) -> bool {
+pub fn engine_limiter_stats() -> LimiterStats {
+    AUDIO.with(|a| a.borrow().master.limiter.stats)
+}
+
+pub fn engine_reset_limiter_stats() {
+    AUDIO.with(|a| a.borrow_mut().master.limiter.stats = LimiterStats::default())
+}
+
+pub fn engine_meter_snapshot() -> MeterSnapshot {
     AUDIO.with(|a| {
-        let sr_pair = {
-            let st = a.borrow();
-            if let Some(tro) = st.tracks.get(track_id) {
-                if let Some(asset) = st.assets.get(&tro.asset_id) {
-                    Some((asset.sr, st.sr))
-                } else { None }
-            } else { None }
-        };
-        let (asset_sr, st_sr) = match sr_pair { Some(v) => v, None => return false };
-        let mut st = a.borrow_mut();
-        if let Some(t) = st.tracks.get_mut(track_id) {
-            t.pos = (offset_samples as f64) * (asset_sr as f64 / st_sr as f64);
-            t.loop_cfg = loop_cfg(loop_mode, loop_start, loop_end, xfade_ms, asset_sr);
-            t.playing = true;
-            true
-        } else { false }
+        let st = a.borrow();
+        let mut buses: Vec<BusLevels> = st.buses.iter().map(|b| BusLevels { bus_id: b.id.to_string(), level: b.meter.reading() }).collect();
+        buses.sort_by(|x, y| x.bus_id.cmp(&y.bus_id));
+        MeterSnapshot {
+            at_samples: st.current_sample,
+            master: MasterLevels { level: st.master.meter.reading(), true_peak_db: lin_to_db(st.master.true_peak.peak_out) },
+            buses,
+            duckers: st.duckers.iter().map(|d| DuckerLevels {
+                target_bus_id: st.buses[d.target_bus].id.to_string(),
+                key_bus_ids: d.keys.iter().map(|k| st.buses[k.bus].id.to_string()).collect(),
+                gain_reduction_db: -lin_to_db(d.gr),
+            }).collect(),
+        }
     })
 }
 
-pub fn engine_set_loop(track_id: &str, loop_mode: &str, loop_start: u32, loop_end: i32, xfade_ms: u32) -> bool {
+pub fn engine_register_asset(id: &str, sample_rate: f32, channels: Vec<Vec<f32>>) -> bool {
+    engine_register_asset_layout(id, sample_rate, channels, "auto", false)
+}
+
+/// Like [`engine_register_asset`], but converts the asset to the engine rate up front with a
+/// windowed-sinc resampler, so playback needs no runtime rate conversion. Loop points and
+/// markers are still given in the asset's original samples.
+pub fn engine_register_asset_resampled(id: &str, sample_rate: f32, channels: Vec<Vec<f32>>) -> bool {
+    engine_register_asset_layout(id, sample_rate, channels, "auto", true)
+}
+
+/// Registers an asset whose channels follow the speaker `layout` (see [`downmix_matrix`];
+/// `auto` picks the usual layout for the channel count, and `equal` for counts with no usual
+/// layout). Assets with more than two channels are folded down to stereo here, so the mixer
+/// only ever sees mono or stereo. Returns false when the layout is unknown or does not match
+/// the number of channels.
+pub fn engine_register_asset_layout(id: &str, sample_rate: f32, channels: Vec<Vec<f32>>, layout: &str, resample: bool) -> bool {
+    let mut ch_vec: Vec<Vec<f32>> = channels;
+    if ch_vec.is_empty() { return false; }
+    let layout = match layout {
+        "" | "auto" => match ch_vec.len() {
+            1 => "mono",
+            2 => "stereo",
+            3 => "3.0",
+            4 => "quad",
+            5 => "5.0",
+            6 => "5.1",
+            7 => "6.1",
+            8 => "7.1",
+            _ => "equal",
+        },
+        l => l,
+    };
+    match (layout, ch_vec.len()) {
+        ("mono", 1) | ("stereo", 2) => {}
+        ("mono", _) | ("stereo", _) => return false,
+        // Unknown speaker positions: every channel into both sides at equal weight
+        ("equal", n) => {
+            let g = 1.0 / n as f32;
+            ch_vec = downmix(&ch_vec, &vec![(g, g); n]);
+        }
+        _ => {
+            let m = match downmix_matrix(layout) { Some(m) if m.len() == ch_vec.len() => m, _ => return false };
+            ch_vec = downmix(&ch_vec, m);
+        }
+    }
     AUDIO.with(|a| {
-        let asset_sr_opt = {
-            let st = a.borrow();
-            if let Some(tro) = st.tracks.get(track_id) {
-                st.assets.get(&tro.asset_id).map(|asst| asst.sr)
-            } else { None }
-        };
-        let asset_sr = match asset_sr_opt { Some(v) => v, None => return false };
         let mut st = a.borrow_mut();
-        if let Some(t) = st.tracks.get_mut(track_id) {
-            t.loop_cfg = loop_cfg(loop_mode, loop_start, loop_end, xfade_ms, asset_sr);
-            true
-        } else { false }
-    })
+        let engine_sr = st.sr;
+        let mut asset = AudioAsset { id: id.into(), sr: sample_rate, src_sr: sample_rate, ch: ch_vec, last_used: Cell::new(0) };
+        if resample && engine_sr > 0.0 && sample_rate > 0.0 && sample_rate != engine_sr {
+            let resampler = SincResampler::new(engine_sr as f64 / sample_rate as f64);
+            asset.ch = asset.ch.iter().map(|c| resampler.process(c)).collect();
+            asset.sr = engine_sr;
+        }
+        let asset = Rc::new(asset);
+        st.touch(&asset);
+        // Replacing: tracks already playing the old PCM keep it until they let go
+        let old_in_use = st.assets.get(id).is_some_and(|old| st.asset_in_use(old));
+        if let Some(old) = st.assets.insert(id.to_string(), asset) {
+            if old_in_use { st.retired.push((old, false)); } else { st.drop_idle_holders(&old); }
+        }
+        st.enforce_budget(Some(id));
+    });
+    true
 }
 
-pub fn engine_set_markers(track_id: &str, markers: Vec<usize>) -> bool {
+/// Removes an asset from the registry. An asset a playing or scheduled track (or an armed
+/// transition) still uses is refused, or with `defer` hidden from new tracks at once and freed
+/// when the last use ends (its tracks end, are stopped or switch to other assets);
+/// `assetUnloaded` reports the moment the memory is released. Ended tracks holding a freed
+/// asset are removed.
+pub fn engine_unload_asset(asset_id: &str, defer: bool) -> bool {
     AUDIO.with(|a| {
         let mut st = a.borrow_mut();
-        let t = match st.tracks.get_mut(track_id) { Some(x) => x, None => return false };
-        let mut m = markers;
-        m.sort_unstable();
-        m.dedup();
-        t.markers = m;
+        let in_use = match st.assets.get(asset_id) {
+            None => return false,
+            Some(x) => st.asset_in_use(x),
+        };
+        if in_use && !defer { return false; }
+        if let Some(asset) = st.assets.remove(asset_id) {
+            if in_use {
+                st.retired.push((asset, true));
+            } else {
+                st.drop_idle_holders(&asset);
+                let at_samples = st.current_sample;
+                push_event(&mut st.events, EngineEvent::AssetUnloaded { asset_id: asset.id.clone(), at_samples });
+            }
+        }
         true
     })
 }
 
-pub fn engine_transition(track_id: &str, at: &str, to_asset_id: &str, loop_mode: &str, loop_start: u32, loop_end: i32, xfade_ms: u32) -> bool {
+/// Caps the PCM kept registered at `bytes` (0 lifts the cap). Above it, assets no track uses
+/// are evicted least recently used first; assets in use are never evicted, so the total can
+/// stay above the budget until they are released.
+pub fn engine_set_memory_budget(bytes: u64) {
     AUDIO.with(|a| {
-        let asset_sr_opt = { let st = a.borrow(); st.assets.get(to_asset_id).map(|asst| asst.sr) };
-        let asset_sr = match asset_sr_opt { Some(v) => v, None => return false };
-        let lc = loop_cfg(loop_mode, loop_start, loop_end, xfade_ms, asset_sr);
-        let mut st = a.borrow_mut();
-        if let Some(t) = st.tracks.get_mut(track_id) {
-            match at {
-                "now" => {
-                    t.asset_id = to_asset_id.to_string();
-                    t.loop_cfg = lc;
-                    t.pos = lc.start as f64;
-                    t.pending_switch = None;
-                    t.pending_switch_at = None;
-                }
-                "loopEnd" => {
-                    t.pending_switch = Some((to_asset_id.to_string(), lc));
-                    t.pending_switch_at = None;
+        let mut st = a.borrow_mut();
+        st.memory_budget = bytes as usize;
+        st.enforce_budget(None);
+    })
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssetInfo {
+    pub asset_id: String,
+    pub bytes: u64,
+    pub in_use: bool,
+    /// Unloaded or replaced, but still held by a track.
+    pub unloading: bool,
+}
+
+/// PCM memory held by the engine, including assets awaiting a deferred unload.
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssetStats {
+    pub total_bytes: u64,
+    pub budget_bytes: u64,
+    pub assets: Vec<AssetInfo>,
+}
+
+pub fn engine_asset_stats() -> AssetStats {
+    AUDIO.with(|a| {
+        let st = a.borrow();
+        let info = |x: &Rc<AudioAsset>, unloading: bool| AssetInfo { asset_id: x.id.to_string(), bytes: x.bytes() as u64, in_use: st.asset_in_use(x), unloading };
+        let mut assets: Vec<AssetInfo> = st.assets.values().map(|x| info(x, false)).chain(st.retired.iter().map(|(x, _)| info(x, true))).collect();
+        assets.sort_by(|x, y| (&x.asset_id, x.unloading).cmp(&(&y.asset_id, y.unloading)));
+        AssetStats { total_bytes: st.asset_bytes() as u64, budget_bytes: st.memory_budget as u64, assets }
+    })
+}
+
+/// Stereo fold-down for multichannel layouts: `(left, right)` gain per input channel, in WAV /
+/// SMPTE channel order (the order `decodeAudioData` produces). Centre and surrounds are folded in
+/// at -3 dB as in ITU-R BS.775 and the LFE is dropped. `ambix` is first-order ambisonics
+/// (ACN/SN3D: W Y Z X) decoded to a pair of virtual cardioids facing left and right.
+fn downmix_matrix(layout: &str) -> Option<&'static [(f32, f32)]> {
+    const H: f32 = std::f32::consts::FRAC_1_SQRT_2;
+    const L: (f32, f32) = (1.0, 0.0);
+    const R: (f32, f32) = (0.0, 1.0);
+    const C: (f32, f32) = (H, H);
+    const LFE: (f32, f32) = (0.0, 0.0);
+    const LS: (f32, f32) = (H, 0.0);
+    const RS: (f32, f32) = (0.0, H);
+    const S: (f32, f32) = (0.5, 0.5); // mono surround / back centre
+    Some(match layout {
+        "3.0" => &[L, R, C],
+        "quad" => &[L, R, LS, RS],
+        "lcrs" | "4.0" => &[L, R, C, S],
+        "5.0" => &[L, R, C, LS, RS],
+        "5.1" => &[L, R, C, LFE, LS, RS],
+        "6.1" => &[L, R, C, LFE, S, LS, RS],
+        "7.1" => &[L, R, C, LFE, LS, RS, LS, RS],
+        "ambix" => &[(0.5, 0.5), (0.5, -0.5), (0.0, 0.0), (0.0, 0.0)],
+        _ => return None,
+    })
+}
+
+fn downmix(ch: &[Vec<f32>], m: &[(f32, f32)]) -> Vec<Vec<f32>> {
+    let len = ch.iter().map(|c| c.len()).min().unwrap_or(0);
+    let mut out = vec![vec![0.0f32; len]; 2];
+    for (c, &(gl, gr)) in ch.iter().zip(m) {
+        for i in 0..len {
+            out[0][i] += c[i] * gl;
+            out[1][i] += c[i] * gr;
+        }
+    }
+    out
+}
+
+/// Half-width of the resampling kernel, in zero crossings of its cutoff.
+const RESAMPLE_ZEROS: f64 = 16.0;
+
+/// Kernel phases tabulated per input sample; positions in between interpolate linearly.
+const RESAMPLE_PHASES: usize = 512;
+
+/// Offline band-limited resampler for one `ratio = to_sr / from_sr`, using a Blackman-windowed
+/// sinc. When downsampling the cutoff drops to the new Nyquist. The kernel is tabulated once as
+/// `RESAMPLE_PHASES + 1` rows of `taps` weights, so each channel only does multiply-adds.
+struct SincResampler {
+    ratio: f64,
+    taps: usize,
+    table: Vec<f64>,
+}
+
+impl SincResampler {
+    fn new(ratio: f64) -> Self {
+        use std::f64::consts::PI;
+        // Cutoff as a fraction of the input Nyquist, a little below it to leave room for the transition band
+        let fc = ratio.min(1.0) * 0.95;
+        let half = RESAMPLE_ZEROS / fc; // kernel half-width in input samples
+        // Row `p` holds the weights for input samples `base - (h - 1) ..= base + h` at `t = base + p / PHASES`
+        let h = half.ceil() as usize;
+        let taps = 2 * h;
+        let mut table = Vec::with_capacity((RESAMPLE_PHASES + 1) * taps);
+        for p in 0..=RESAMPLE_PHASES {
+            let frac = p as f64 / RESAMPLE_PHASES as f64;
+            table.extend((0..taps).map(|j| {
+                let d = frac + (h - 1) as f64 - j as f64;
+                let u = d / half;
+                if u.abs() >= 1.0 { return 0.0; }
+                let w = 0.42 + 0.5 * (PI * u).cos() + 0.08 * (2.0 * PI * u).cos();
+                let arg = PI * fc * d;
+                let sinc = if arg.abs() < 1e-12 { 1.0 } else { arg.sin() / arg };
+                fc * sinc * w
+            }));
+        }
+        SincResampler { ratio, taps, table }
+    }
+
+    fn process(&self, x: &[f32]) -> Vec<f32> {
+        let out_len = (x.len() as f64 * self.ratio).round() as usize;
+        if x.is_empty() { return vec![0.0; out_len]; }
+        let h = self.taps / 2;
+        (0..out_len)
+            .map(|n| {
+                let t = n as f64 / self.ratio;
+                let base = t.floor();
+                let pf = (t - base) * RESAMPLE_PHASES as f64;
+                let p = (pf as usize).min(RESAMPLE_PHASES - 1);
+                let mix = pf - p as f64;
+                let (r0, r1) = (&self.table[p * self.taps..(p + 1) * self.taps], &self.table[(p + 1) * self.taps..(p + 2) * self.taps]);
+                // First input sample under the kernel; taps falling outside `x` are skipped
+                let first = base as isize - (h as isize - 1);
+                let lo = (-first).max(0) as usize;
+                let hi = (x.len() as isize - first).clamp(0, self.taps as isize) as usize;
+                let mut acc = 0.0f64;
+                for j in lo..hi {
+                    let w = r0[j] + (r1[j] - r0[j]) * mix;
+                    acc += x[(first + j as isize) as usize] as f64 * w;
                 }
-                "nextMarker" => {
-                    let idx = t.pos.floor() as usize;
-                    if let Some(next) = t.markers.iter().copied().find(|&m| m > idx) {
-                        t.pending_switch = Some((to_asset_id.to_string(), lc));
-                        t.pending_switch_at = Some(next);
-                    } else {
-                        t.pending_switch = Some((to_asset_id.to_string(), lc));
-                        t.pending_switch_at = None;
-                    }
+                acc as f32
+            })
+            .collect()
+    }
+}
+
+fn new_track(track_id: &str, bus: usize, asset: Rc<AudioAsset>, step: f64, pan: f32, gain_db: f32) -> AudioTrack {
+    let pan = pan.clamp(-1.0, 1.0);
+    let (pl, pr) = pan_coeffs(pan, asset.ch.len() > 1);
+    AudioTrack {
+        id: track_id.into(),
+        bus,
+        asset,
+        pos: 0.0,
+        step,
+        gain: Ramp::new(db_to_lin(gain_db)),
+        pan: Ramp::new(pan),
+        pan_l: pl,
+        pan_r: pr,
+        playing: false,
+        loop_cfg: LoopCfg::default(),
+        markers: Vec::new(),
+        tempo: None,
+        marker_cursor: 0,
+        loop_count: 0,
+        pending_switch: None,
+        pending_switch_at: None,
+        pending_fade: None,
+        tail: None,
+        fade: Ramp::new(1.0),
+        stopping: false,
+        lpf: Lpf::default(),
+        interp: None,
+        started_at: 0,
+        rendered_at: u64::MAX,
+    }
+}
+
+impl AudioTrack {
+    fn start(&mut self, offset: u32, loop_cfg: LoopCfg, engine_sr: f32, events: &mut Vec<EngineEvent>, at_samples: u64) {
+        self.pos = (offset as f64) * (self.asset.sr as f64 / engine_sr as f64);
+        self.loop_cfg = loop_cfg.fit(&self.asset);
+        self.playing = true;
+        self.stopping = false;
+        self.fade = Ramp::new(1.0);
+        self.loop_count = 0;
+        self.started_at = at_samples;
+        self.tail = None;
+        self.marker_cursor = self.markers.partition_point(|&(m, _)| (m as f64) < self.pos);
+        push_event(events, EngineEvent::TrackStarted { track_id: self.id.clone(), at_samples });
+    }
+
+    /// Points the track at `asset` with loop `lc` and moves the playhead to the loop start,
+    /// dropping any armed transition.
+    fn switch_asset(&mut self, asset: Rc<AudioAsset>, lc: LoopCfg, engine_sr: f32, events: &mut Vec<EngineEvent>, at_samples: u64) {
+        push_event(events, EngineEvent::TransitionCompleted { track_id: self.id.clone(), asset_id: asset.id.clone(), at_samples });
+        self.load_asset(asset, lc, engine_sr);
+    }
+
+    /// Like `switch_asset`, but the current asset keeps playing from where it is and fades out
+    /// over `len` samples while the new one fades in. The transition completes when the fade does.
+    fn crossfade_to(&mut self, asset: Rc<AudioAsset>, lc: LoopCfg, len: usize, engine_sr: f32) {
+        self.tail = Some(FadeTail { asset: self.asset.clone(), pos: self.pos, step: self.step, loop_cfg: self.loop_cfg, done: 0, len });
+        self.load_asset(asset, lc, engine_sr);
+    }
+
+    fn load_asset(&mut self, asset: Rc<AudioAsset>, lc: LoopCfg, engine_sr: f32) {
+        self.step = (asset.sr / engine_sr) as f64;
+        self.loop_cfg = lc;
+        self.pos = lc.start as f64;
+        self.pending_switch = None;
+        self.pending_switch_at = None;
+        self.pending_fade = None;
+        self.marker_cursor = self.markers.partition_point(|&(m, _)| m < lc.start);
+        (self.pan_l, self.pan_r) = pan_coeffs(self.pan.value, asset.ch.len() > 1);
+        self.asset = asset;
+    }
+}
+
+pub fn engine_create_track(track_id: &str, asset_id: &str, pan: f32, gain_db: f32) -> bool {
+    engine_create_track_bus(track_id, "sfx", asset_id, pan, gain_db)
+}
+
+pub fn engine_create_track_bus(track_id: &str, bus: &str, asset_id: &str, pan: f32, gain_db: f32) -> bool {
+    AUDIO.with(|a| {
+        let mut st = a.borrow_mut();
+        let asset = match st.assets.get(asset_id) { Some(x) => x.clone(), None => return false };
+        st.touch(&asset);
+        let step = (asset.sr / st.sr) as f64;
+        // Tracks routed to a bus that was never created get a unity-gain bus
+        let bus = st.bus_slot(bus);
+        let t = new_track(track_id, bus, asset, step, pan, gain_db);
+        st.insert_track(t);
+        true
+    })
+}
+
+/// Queues `cmd` at engine sample `when`; commands due now or in the past apply immediately.
+fn schedule(st: &mut AudioState, when: u64, cmd: Cmd) {
+    if when <= st.current_sample {
+        apply_cmd(st, cmd);
+        return;
+    }
+    let seq = st.next_seq;
+    st.next_seq += 1;
+    let idx = st.timeline.partition_point(|e| (e.when, e.seq) < (when, seq));
+    st.timeline.insert(idx, Scheduled { when, seq, cmd });
+}
+
+fn apply_cmd(st: &mut AudioState, cmd: Cmd) {
+    let engine_sr = st.sr;
+    match cmd {
+        Cmd::Play { track, offset, loop_cfg } => {
+            let slot = match st.track_index.get(&track) { Some(&i) => i, None => return };
+            if !admit_voice(st, slot, offset, loop_cfg) { return; }
+            let now = st.current_sample;
+            if let Some(t) = st.tracks[slot].as_mut() { t.start(offset, loop_cfg, engine_sr, &mut st.events, now); }
+        }
+        Cmd::Stop { track, fade } => stop_track(st, &track, fade),
+        Cmd::SetLoop { track, loop_cfg } => {
+            if let Some(t) = st.track_mut(&track) { t.loop_cfg = loop_cfg.fit(&t.asset); }
+        }
+        Cmd::Transition { track, at, asset, loop_cfg, fade } => {
+            let now = st.current_sample;
+            let slot = match st.track_index.get(&track) { Some(&i) => i, None => return };
+            let t = match st.tracks[slot].as_mut() { Some(x) => x, None => return };
+            let point = switch_point(t, at);
+            arm_transition(t, at, point, asset, loop_cfg, fade, engine_sr, &mut st.events, now);
+        }
+        Cmd::TrackGain { track, gain, ramp } => {
+            if let Some(t) = st.track_mut(&track) { t.gain.set_target(gain, ramp); }
+        }
+        Cmd::TrackPan { track, pan, ramp } => {
+            if let Some(t) = st.track_mut(&track) {
+                t.pan.set_target(pan, ramp);
+                if !t.pan.is_ramping() {
+                    let (pl, pr) = pan_coeffs(t.pan.value, t.asset.ch.len() > 1);
+                    t.pan_l = pl;
+                    t.pan_r = pr;
                 }
-                _ => {}
             }
+        }
+        Cmd::BusGain { bus, gain, ramp } => {
+            if let Some(b) = st.bus_mut(&bus) { b.gain.set_target(gain, ramp); }
+        }
+        // Stems bypass the bus voice limit: admitting only some of them would break the lock
+        Cmd::GroupPlay { tracks, offset, loop_cfg } => {
+            let now = st.current_sample;
+            for track in &tracks {
+                let slot = match st.track_index.get(track) { Some(&i) => i, None => continue };
+                if let Some(t) = st.tracks[slot].as_mut() { t.start(offset, loop_cfg, engine_sr, &mut st.events, now); }
+            }
+        }
+        Cmd::GroupStop { tracks, fade } => {
+            for track in &tracks { stop_track(st, track, fade); }
+        }
+        Cmd::GroupTransition { tracks, at, loop_cfg, fade } => {
+            let now = st.current_sample;
+            // The leader's playhead picks the switch point so every stem switches on the same frame
+            let point = tracks.first().and_then(|(id, _)| st.track(id)).and_then(|t| switch_point(t, at));
+            for (track, asset) in tracks {
+                let slot = match st.track_index.get(&track) { Some(&i) => i, None => continue };
+                if let Some(t) = st.tracks[slot].as_mut() { arm_transition(t, at, point, asset, loop_cfg, fade, engine_sr, &mut st.events, now); }
+            }
+        }
+        Cmd::StemGains { gains, ramp } => {
+            for (track, gain) in &gains {
+                if let Some(t) = st.track_mut(track) { t.gain.set_target(*gain, ramp); }
+            }
+        }
+    }
+}
+
+fn stop_track(st: &mut AudioState, track: &str, fade: usize) {
+    let t = match st.track_mut(track) { Some(x) => x, None => return };
+    if !t.playing {
+        st.remove_track(track);
+        return;
+    }
+    t.fade.set_target(0.0, fade);
+    t.stopping = true;
+}
+
+/// Asset position a marker or grid transition switches at: the first marker, beat or bar line
+/// strictly ahead of the playhead, or the start of bar N if it is still ahead. None for the
+/// other kinds, and when there is no such point (no markers or tempo map, bar N passed).
+fn switch_point(t: &AudioTrack, at: TransitionAt) -> Option<usize> {
+    let idx = t.pos.floor() as usize;
+    if at == TransitionAt::NextMarker { return t.markers.iter().map(|&(m, _)| m).find(|&m| m > idx); }
+    let tm = t.tempo?;
+    let (beat, downbeat) = tm.grid(&t.asset);
+    let bar = beat * tm.beats_per_bar as f64;
+    let point = match at {
+        TransitionAt::NextBeat => downbeat + (((idx as f64 - downbeat) / beat).floor() + 1.0) * beat,
+        TransitionAt::NextBar => downbeat + (((idx as f64 - downbeat) / bar).floor() + 1.0) * bar,
+        TransitionAt::Bar(n) => downbeat + (n - 1) as f64 * bar,
+        _ => return None,
+    }
+    .round();
+    (point > idx as f64).then_some(point as usize)
+}
+
+/// Switches `t` to `asset` now, or arms the switch for `point` (see [`switch_point`]) or for its
+/// loop end. With `fade` the switch crossfades instead of cutting.
+#[allow(clippy::too_many_arguments)]
+fn arm_transition(t: &mut AudioTrack, at: TransitionAt, point: Option<usize>, asset: Rc<AudioAsset>, lc: LoopCfg, fade: Option<SwitchFade>, engine_sr: f32, events: &mut Vec<EngineEvent>, now: u64) {
+    t.pending_fade = fade;
+    match at {
+        // A fade cannot start in the past, so a crossfade `now` starts now whatever its alignment
+        TransitionAt::Now => match fade {
+            Some(f) if t.playing => t.crossfade_to(asset, lc, f.len, engine_sr),
+            _ => t.switch_asset(asset, lc, engine_sr, events, now),
+        },
+        TransitionAt::LoopEnd => {
+            t.pending_switch = Some((asset, lc));
+            t.pending_switch_at = None;
+        }
+        _ => {
+            // Without a point ahead the switch falls back to the loop end
+            t.pending_switch_at = point;
+            t.pending_switch = Some((asset, lc));
+        }
+    }
+}
+
+/// Applies the bus voice limit to a play request for the track in `slot`. Returns false when
+/// the request was refused or queued rather than started now.
+fn admit_voice(st: &mut AudioState, slot: usize, offset: u32, loop_cfg: LoopCfg) -> bool {
+    let (bus, restart) = match &st.tracks[slot] { Some(t) => (t.bus, t.playing && !t.stopping), None => return false };
+    let b = &st.buses[bus];
+    // Restarting a voice that is already sounding does not take another one
+    if b.max_voices == 0 || restart { return true; }
+    let mut active = 0;
+    let mut oldest: Option<usize> = None;
+    for (i, t) in st.tracks.iter().enumerate() {
+        let t = match t { Some(t) if t.bus == bus && t.playing && !t.stopping => t, _ => continue };
+        active += 1;
+        if oldest.is_none_or(|o| st.tracks[o].as_ref().is_some_and(|ot| t.started_at < ot.started_at)) { oldest = Some(i); }
+    }
+    if active < b.max_voices { return true; }
+    let now = st.current_sample;
+    let bus_id = b.id.clone();
+    let new_id = st.tracks[slot].as_ref().map(|t| t.id.clone());
+    match b.policy {
+        VoicePolicy::DropOldest => {
+            let steal = ms_to_samples(STEAL_FADE_MS, st.sr).max(1);
+            let victim = oldest.and_then(|o| st.tracks[o].as_mut());
+            let dropped = victim.map(|v| {
+                v.fade.set_target(0.0, steal);
+                v.stopping = true;
+                v.id.clone()
+            });
+            push_event(&mut st.events, EngineEvent::PolyphonyLimited { bus_id, dropped_track_id: dropped, at_samples: now });
             true
-        } else { false }
+        }
+        VoicePolicy::Queue if st.buses[bus].queue.len() < MAX_QUEUED_VOICES => {
+            if let Some(track) = new_id {
+                st.buses[bus].queue.push_back(QueuedPlay { track, offset, loop_cfg });
+            }
+            push_event(&mut st.events, EngineEvent::PolyphonyLimited { bus_id, dropped_track_id: None, at_samples: now });
+            false
+        }
+        VoicePolicy::DropNewest | VoicePolicy::Queue => {
+            if let Some(id) = &new_id { st.remove_track(id); }
+            push_event(&mut st.events, EngineEvent::PolyphonyLimited { bus_id, dropped_track_id: new_id, at_samples: now });
+            false
+        }
+    }
+}
+
+/// Loop config for `track_id` against the asset it currently plays.
+fn track_loop_cfg(st: &AudioState, track_id: &str, loop_mode: &str, loop_start: u32, loop_end: i32, xfade_ms: u32) -> Option<LoopCfg> {
+    let asset = &st.track(track_id)?.asset;
+    loop_cfg(loop_mode, loop_start, loop_end, xfade_ms, asset)
+}
+
+/// Takes every event queued by the render path since the last drain, oldest first. Allocates
+/// only when there are events; use `engine_drain_events_into` to reuse a buffer.
+pub fn engine_drain_events() -> Vec<EngineEvent> {
+    let mut out = Vec::new();
+    engine_drain_events_into(&mut out);
+    out
+}
+
+/// Appends the queued events to `out`; the engine's own queue keeps its capacity, so with
+/// `out` reserved (up to `MAX_PENDING_EVENTS`) a process + drain cycle does not allocate.
+pub fn engine_drain_events_into(out: &mut Vec<EngineEvent>) {
+    AUDIO.with(|a| {
+        let mut st = a.borrow_mut();
+        if !st.events.is_empty() { out.append(&mut st.events); }
+    })
+}
+
+/// Number of events waiting to be drained.
+pub fn engine_pending_events() -> usize {
+    AUDIO.with(|a| a.borrow().events.len())
+}
+
+/// Copy of the queued events without draining them.
+#[cfg_attr(not(feature = "ffi"), allow(dead_code))]
+pub fn engine_peek_events() -> Vec<EngineEvent> {
+    AUDIO.with(|a| a.borrow().events.clone())
+}
+
+/// Engine sample clock: output samples rendered since `engine_init`.
+pub fn engine_current_sample() -> u64 {
+    AUDIO.with(|a| a.borrow().current_sample)
+}
+
+pub fn engine_schedule_play(track_id: &str, offset_samples: u32, loop_mode: &str, loop_start: u32, loop_end: i32, xfade_ms: u32) -> bool {
+    engine_play_at(track_id, 0, offset_samples, loop_mode, loop_start, loop_end, xfade_ms)
+}
+
+/// Starts a track at engine sample `when_samples` (anything in the past means now).
+#[allow(clippy::too_many_arguments)]
+pub fn engine_play_at(track_id: &str, when_samples: u64, offset_samples: u32, loop_mode: &str, loop_start: u32, loop_end: i32, xfade_ms: u32) -> bool {
+    AUDIO.with(|a| {
+        let mut st = a.borrow_mut();
+        let lc = match track_loop_cfg(&st, track_id, loop_mode, loop_start, loop_end, xfade_ms) { Some(v) => v, None => return false };
+        schedule(&mut st, when_samples, Cmd::Play { track: track_id.to_string(), offset: offset_samples, loop_cfg: lc });
+        true
+    })
+}
+
+pub fn engine_set_loop(track_id: &str, loop_mode: &str, loop_start: u32, loop_end: i32, xfade_ms: u32) -> bool {
+    engine_set_loop_at(track_id, 0, loop_mode, loop_start, loop_end, xfade_ms)
+}
+
+pub fn engine_set_loop_at(track_id: &str, when_samples: u64, loop_mode: &str, loop_start: u32, loop_end: i32, xfade_ms: u32) -> bool {
+    AUDIO.with(|a| {
+        let mut st = a.borrow_mut();
+        let lc = match track_loop_cfg(&st, track_id, loop_mode, loop_start, loop_end, xfade_ms) { Some(v) => v, None => return false };
+        schedule(&mut st, when_samples, Cmd::SetLoop { track: track_id.to_string(), loop_cfg: lc });
+        true
+    })
+}
+
+pub fn engine_set_markers(track_id: &str, markers: Vec<usize>) -> bool {
+    AUDIO.with(|a| {
+        let mut st = a.borrow_mut();
+        let t = match st.track_mut(track_id) { Some(x) => x, None => return false };
+        set_track_markers(t, &markers);
+        true
+    })
+}
+
+fn set_track_markers(t: &mut AudioTrack, markers: &[usize]) {
+    let mut m: Vec<(usize, usize)> = markers.iter().map(|&x| (t.asset.to_asset_pos(x), x)).collect();
+    m.sort_unstable();
+    m.dedup_by_key(|e| e.0);
+    t.marker_cursor = m.partition_point(|&(x, _)| (x as f64) < t.pos);
+    t.markers = m;
+}
+
+/// Gives a track a tempo map for `nextBeat`/`nextBar`/`bar:N` transitions and position queries.
+/// `downbeat_samples` is where bar 1 starts in the asset's source samples; `bpm` counts beats of
+/// the `beat_unit` note. A `bpm` of zero removes the map.
+pub fn engine_set_tempo(track_id: &str, bpm: f64, beats_per_bar: u32, beat_unit: u32, downbeat_samples: f64) -> bool {
+    AUDIO.with(|a| {
+        let mut st = a.borrow_mut();
+        let t = match st.track_mut(track_id) { Some(x) => x, None => return false };
+        if bpm == 0.0 {
+            t.tempo = None;
+            return true;
+        }
+        if !(bpm.is_finite() && bpm > 0.0) || beats_per_bar == 0 || beat_unit == 0 || !downbeat_samples.is_finite() { return false; }
+        t.tempo = Some(TempoMap { bpm, beats_per_bar, beat_unit, downbeat: downbeat_samples });
+        true
+    })
+}
+
+/// Where a track's playhead sits on its tempo grid. Bars and beats count from 1; bars before
+/// the first downbeat (a pickup) are 0 or negative.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MusicalPosition {
+    pub bar: i64,
+    pub beat: u32,
+    pub beat_fraction: f64,
+    pub beats_per_bar: u32,
+    pub beat_unit: u32,
+    pub bpm: f64,
+    pub at_samples: u64,
+    /// Engine samples until the next beat, assuming no loop wrap or transition comes first.
+    pub samples_to_next_beat: u64,
+    /// Engine samples until the next bar line, on the same assumption.
+    pub samples_to_next_bar: u64,
+}
+
+/// Current bar and beat of a playing track with a tempo map.
+pub fn engine_musical_position(track_id: &str) -> Option<MusicalPosition> {
+    AUDIO.with(|a| {
+        let st = a.borrow();
+        let t = st.track(track_id).filter(|t| t.playing)?;
+        let tm = t.tempo?;
+        let (beat_len, downbeat) = tm.grid(&t.asset);
+        let beats = (t.pos - downbeat) / beat_len;
+        let whole = beats.floor();
+        let bpb = tm.beats_per_bar as i64;
+        let beat_idx = whole as i64;
+        let to_engine = |asset_samples: f64| (asset_samples / t.step).ceil().max(0.0) as u64;
+        let next_bar = (beat_idx.div_euclid(bpb) + 1) * bpb;
+        Some(MusicalPosition {
+            bar: beat_idx.div_euclid(bpb) + 1,
+            beat: beat_idx.rem_euclid(bpb) as u32 + 1,
+            beat_fraction: beats - whole,
+            beats_per_bar: tm.beats_per_bar,
+            beat_unit: tm.beat_unit,
+            bpm: tm.bpm,
+            at_samples: st.current_sample,
+            samples_to_next_beat: to_engine((whole + 1.0 - beats) * beat_len),
+            samples_to_next_bar: to_engine((next_bar as f64 - beats) * beat_len),
+        })
+    })
+}
+
+pub fn engine_transition(track_id: &str, at: &str, to_asset_id: &str, loop_mode: &str, loop_start: u32, loop_end: i32, xfade_ms: u32) -> bool {
+    engine_transition_at(track_id, 0, at, to_asset_id, loop_mode, loop_start, loop_end, xfade_ms)
+}
+
+/// Arms a transition at engine sample `when_samples`; `at` (`now`, `loopEnd`, `nextMarker`,
+/// `nextBeat`, `nextBar`, `bar:N`) is resolved against the track's playhead at that sample.
+#[allow(clippy::too_many_arguments)]
+pub fn engine_transition_at(track_id: &str, when_samples: u64, at: &str, to_asset_id: &str, loop_mode: &str, loop_start: u32, loop_end: i32, xfade_ms: u32) -> bool {
+    engine_transition_fade_at(track_id, when_samples, at, to_asset_id, loop_mode, loop_start, loop_end, xfade_ms, 0.0, "before")
+}
+
+/// Like [`engine_transition_at`], but the switch is an equal-power crossfade of `fade_ms`
+/// (`xfade_ms` stays the new loop's own crossfade). `fade_align` `before` ends the fade on the
+/// boundary, `center` centres it there; either way the incoming asset is pre-rolled so its loop
+/// start lands on the boundary. A switch `now` fades from now. The outgoing asset plays on under
+/// the fade and `transitionCompleted` is reported when the fade ends.
+#[allow(clippy::too_many_arguments)]
+pub fn engine_transition_fade_at(track_id: &str, when_samples: u64, at: &str, to_asset_id: &str, loop_mode: &str, loop_start: u32, loop_end: i32, xfade_ms: u32, fade_ms: f32, fade_align: &str) -> bool {
+    let at = match TransitionAt::parse(at) { Some(v) => v, None => return false };
+    AUDIO.with(|a| {
+        let mut st = a.borrow_mut();
+        if !st.track_index.contains_key(track_id) { return false; }
+        let fade = match switch_fade(fade_ms, fade_align, st.sr) { Some(f) => f, None => return false };
+        let asset = match st.assets.get(to_asset_id) { Some(x) => x.clone(), None => return false };
+        st.touch(&asset);
+        let lc = match loop_cfg(loop_mode, loop_start, loop_end, xfade_ms, &asset) { Some(v) => v, None => return false };
+        schedule(&mut st, when_samples, Cmd::Transition { track: track_id.to_string(), at, asset, loop_cfg: lc, fade });
+        true
+    })
+}
+
+/// Stems can only share a playhead when they have the same rate and length.
+fn same_timeline(a: &AudioAsset, b: &AudioAsset) -> bool {
+    a.sr == b.sr && a.ch[0].len() == b.ch[0].len()
+}
+
+/// Groups existing tracks into stems of one piece, replacing any group with the same id. The
+/// tracks must play assets of equal rate and length; they adopt the first track's markers and
+/// tempo map.
+pub fn engine_create_group(group_id: &str, track_ids: Vec<String>) -> bool {
+    AUDIO.with(|a| {
+        let mut st = a.borrow_mut();
+        let lead = match track_ids.first().and_then(|id| st.track(id)) { Some(t) => t, None => return false };
+        let (lead_asset, tempo, markers) = (lead.asset.clone(), lead.tempo, lead.markers.iter().map(|&(_, m)| m).collect::<Vec<_>>());
+        if !track_ids.iter().all(|id| st.track(id).is_some_and(|t| same_timeline(&t.asset, &lead_asset))) { return false; }
+        for id in &track_ids {
+            if let Some(t) = st.track_mut(id) {
+                set_track_markers(t, &markers);
+                t.tempo = tempo;
+            }
+        }
+        st.groups.insert(group_id.to_string(), MusicGroup { members: track_ids });
+        true
+    })
+}
+
+/// Member track ids of `group_id`, if the group exists and all its tracks are still alive.
+fn group_tracks(st: &AudioState, group_id: &str) -> Option<Vec<String>> {
+    let g = st.groups.get(group_id)?;
+    g.members.iter().all(|id| st.track_index.contains_key(id)).then(|| g.members.clone())
+}
+
+pub fn engine_set_group_markers(group_id: &str, markers: Vec<usize>) -> bool {
+    AUDIO.with(|a| {
+        let mut st = a.borrow_mut();
+        let tracks = match group_tracks(&st, group_id) { Some(v) => v, None => return false };
+        for id in &tracks {
+            if let Some(t) = st.track_mut(id) { set_track_markers(t, &markers); }
+        }
+        true
+    })
+}
+
+/// Starts every stem of the group at engine sample `when_samples`.
+#[allow(clippy::too_many_arguments)]
+pub fn engine_group_play_at(group_id: &str, when_samples: u64, offset_samples: u32, loop_mode: &str, loop_start: u32, loop_end: i32, xfade_ms: u32) -> bool {
+    AUDIO.with(|a| {
+        let mut st = a.borrow_mut();
+        let tracks = match group_tracks(&st, group_id) { Some(v) => v, None => return false };
+        let lc = match track_loop_cfg(&st, &tracks[0], loop_mode, loop_start, loop_end, xfade_ms) { Some(v) => v, None => return false };
+        schedule(&mut st, when_samples, Cmd::GroupPlay { tracks, offset: offset_samples, loop_cfg: lc });
+        true
+    })
+}
+
+pub fn engine_group_stop(group_id: &str, when_samples: u64, fade_out_ms: f32) -> bool {
+    AUDIO.with(|a| {
+        let mut st = a.borrow_mut();
+        let tracks = match group_tracks(&st, group_id) { Some(v) => v, None => return false };
+        let fade = ms_to_samples(fade_out_ms, st.sr);
+        schedule(&mut st, when_samples, Cmd::GroupStop { tracks, fade });
+        true
+    })
+}
+
+/// Switches every stem to its own asset (`to_asset_ids` in member order) on one shared frame;
+/// marker and grid points are resolved against the first stem's playhead. The new assets must
+/// again share rate and length. `fade_ms`/`fade_align` crossfade the switch as in
+/// [`engine_transition_fade_at`]; a `fade_ms` of 0 cuts.
+#[allow(clippy::too_many_arguments)]
+pub fn engine_group_transition_at(group_id: &str, when_samples: u64, at: &str, to_asset_ids: Vec<String>, loop_mode: &str, loop_start: u32, loop_end: i32, xfade_ms: u32, fade_ms: f32, fade_align: &str) -> bool {
+    let at = match TransitionAt::parse(at) { Some(v) => v, None => return false };
+    AUDIO.with(|a| {
+        let mut st = a.borrow_mut();
+        let tracks = match group_tracks(&st, group_id) { Some(v) => v, None => return false };
+        let fade = match switch_fade(fade_ms, fade_align, st.sr) { Some(f) => f, None => return false };
+        if to_asset_ids.len() != tracks.len() { return false; }
+        let assets: Option<Vec<Rc<AudioAsset>>> = to_asset_ids.iter().map(|id| st.assets.get(id).cloned()).collect();
+        let assets = match assets { Some(v) => v, None => return false };
+        if !assets.iter().all(|x| same_timeline(x, &assets[0])) { return false; }
+        for x in &assets { st.touch(x); }
+        let lc = match loop_cfg(loop_mode, loop_start, loop_end, xfade_ms, &assets[0]) { Some(v) => v, None => return false };
+        schedule(&mut st, when_samples, Cmd::GroupTransition { tracks: tracks.into_iter().zip(assets).collect(), at, loop_cfg: lc, fade });
+        true
+    })
+}
+
+/// Sets the gain of several stems on the same sample. Every track must belong to the group.
+pub fn engine_set_stem_gains_at(group_id: &str, when_samples: u64, gains_db: Vec<(String, f32)>, ramp_ms: f32) -> bool {
+    AUDIO.with(|a| {
+        let mut st = a.borrow_mut();
+        let tracks = match group_tracks(&st, group_id) { Some(v) => v, None => return false };
+        if !gains_db.iter().all(|(id, _)| tracks.contains(id)) { return false; }
+        let ramp = ms_to_samples(ramp_ms, st.sr);
+        let gains = gains_db.into_iter().map(|(id, db)| (id, db_to_lin(db))).collect();
+        schedule(&mut st, when_samples, Cmd::StemGains { gains, ramp });
+        true
+    })
+}
+
+/// Creates a bus, or updates the gain of an existing one without resetting its state.
+pub fn engine_create_bus(bus_id: &str, gain_db: f32) -> bool {
+    AUDIO.with(|a| {
+        let mut st = a.borrow_mut();
+        match st.bus_mut(bus_id) {
+            Some(b) => b.gain.set_target(db_to_lin(gain_db), 0),
+            None => {
+                st.buses.push(Bus::new(bus_id, gain_db));
+                let slot = st.buses.len() - 1;
+                st.bus_index.insert(bus_id.to_string(), slot);
+            }
+        }
+        true
+    })
+}
+
+pub fn engine_set_bus_gain(bus_id: &str, gain_db: f32, ramp_ms: f32) -> bool {
+    engine_set_bus_gain_at(bus_id, 0, gain_db, ramp_ms)
+}
+
+pub fn engine_set_bus_gain_at(bus_id: &str, when_samples: u64, gain_db: f32, ramp_ms: f32) -> bool {
+    AUDIO.with(|a| {
+        let mut st = a.borrow_mut();
+        if !st.bus_index.contains_key(bus_id) { return false; }
+        let ramp = ms_to_samples(ramp_ms, st.sr);
+        schedule(&mut st, when_samples, Cmd::BusGain { bus: bus_id.to_string(), gain: db_to_lin(gain_db), ramp });
+        true
+    })
+}
+
+pub fn engine_set_bus_mute(bus_id: &str, mute: bool) -> bool {
+    AUDIO.with(|a| {
+        let mut st = a.borrow_mut();
+        let b = match st.bus_mut(bus_id) { Some(x) => x, None => return false };
+        b.mute = mute;
+        true
+    })
+}
+
+/// While any bus is soloed, every bus that is not soloed is silenced.
+pub fn engine_set_bus_solo(bus_id: &str, solo: bool) -> bool {
+    AUDIO.with(|a| {
+        let mut st = a.borrow_mut();
+        let b = match st.bus_mut(bus_id) { Some(x) => x, None => return false };
+        b.solo = solo;
+        true
+    })
+}
+
+/// Caps how many voices (playing tracks that are not already fading out) a bus sounds at once;
+/// `max_voices = 0` removes the cap. `policy` is `dropOldest` (default), `dropNewest` or `queue`
+/// and decides what happens to a play request beyond the cap. Voices already playing are kept;
+/// raising the cap starts queued plays into the new room, and leaving `queue` refuses the rest
+/// like `dropNewest` (their tracks are removed and `polyphonyLimited` reports each).
+pub fn engine_set_polyphony(bus_id: &str, max_voices: u32, policy: &str) -> bool {
+    let policy = match policy {
+        "" | "dropOldest" => VoicePolicy::DropOldest,
+        "dropNewest" => VoicePolicy::DropNewest,
+        "queue" => VoicePolicy::Queue,
+        _ => return false,
+    };
+    AUDIO.with(|a| {
+        let mut st = a.borrow_mut();
+        let bus = match st.bus_index.get(bus_id) { Some(&i) => i, None => return false };
+        st.buses[bus].max_voices = max_voices as usize;
+        start_queued(&mut st, bus);
+        st.buses[bus].policy = policy;
+        // Plays still waiting are refused as `dropNewest` would have
+        if policy != VoicePolicy::Queue {
+            let now = st.current_sample;
+            let bus_id = st.buses[bus].id.clone();
+            while let Some(q) = st.buses[bus].queue.pop_front() {
+                st.remove_track(&q.track);
+                push_event(&mut st.events, EngineEvent::PolyphonyLimited { bus_id: bus_id.clone(), dropped_track_id: Some(q.track), at_samples: now });
+            }
+        }
+        true
+    })
+}
+
+/// Starts queued plays on `bus`, oldest request first, while it has voices free.
+fn start_queued(st: &mut AudioState, bus: usize) {
+    let now = st.current_sample;
+    let engine_sr = st.sr;
+    loop {
+        let max = st.buses[bus].max_voices;
+        let active = st.tracks.iter().flatten().filter(|t| t.bus == bus && t.playing && !t.stopping).count();
+        if max != 0 && active >= max { break; }
+        let q = match st.buses[bus].queue.pop_front() { Some(q) => q, None => break };
+        let slot = match st.track_index.get(&*q.track) { Some(&i) => i, None => continue };
+        if let Some(t) = st.tracks[slot].as_mut() { t.start(q.offset, q.loop_cfg, engine_sr, &mut st.events, now); }
+    }
+}
+
+/// Sets the low-pass on a bus or a track (`scope` is `"bus"` or `"track"`), mirroring the
+/// worklet's `setLPF` message. `order` 1 = 12 dB/oct, 2 = 24 dB/oct; `cutoff_hz <= 0` disables it.
+pub fn engine_set_lpf(scope: &str, id: &str, cutoff_hz: f32, q: f32, ramp_ms: f32, order: u8) -> bool {
+    AUDIO.with(|a| {
+        let mut st = a.borrow_mut();
+        let sr = st.sr;
+        let ramp = ms_to_samples(ramp_ms, sr);
+        let lpf = match scope {
+            "bus" => match st.bus_mut(id) { Some(b) => &mut b.lpf, None => return false },
+            "track" => match st.track_mut(id) { Some(t) => &mut t.lpf, None => return false },
+            _ => return false,
+        };
+        lpf.set(cutoff_hz, q, ramp, order, sr);
+        true
+    })
+}
+
+/// Engine-wide interpolator (`nearest`, `linear`, `hermite`, `sinc`) for tracks without their own.
+pub fn engine_set_interpolation(mode: &str) -> bool {
+    let interp = match Interp::parse(mode) { Some(i) => i, None => return false };
+    AUDIO.with(|a| a.borrow_mut().interp = interp);
+    true
+}
+
+/// Per-track interpolator; `default` (or an empty string) returns the track to the engine setting.
+pub fn engine_set_track_interpolation(track_id: &str, mode: &str) -> bool {
+    let interp = match mode {
+        "" | "default" => None,
+        m => match Interp::parse(m) { Some(i) => Some(i), None => return false },
+    };
+    AUDIO.with(|a| {
+        let mut st = a.borrow_mut();
+        let t = match st.track_mut(track_id) { Some(x) => x, None => return false };
+        t.interp = interp;
+        true
+    })
+}
+
+/// Ramps a track's gain to `gain_db` over `ramp_ms` (0 = immediate).
+pub fn engine_set_track_gain(track_id: &str, gain_db: f32, ramp_ms: f32) -> bool {
+    engine_set_track_gain_at(track_id, 0, gain_db, ramp_ms)
+}
+
+pub fn engine_set_track_gain_at(track_id: &str, when_samples: u64, gain_db: f32, ramp_ms: f32) -> bool {
+    AUDIO.with(|a| {
+        let mut st = a.borrow_mut();
+        if !st.track_index.contains_key(track_id) { return false; }
+        let ramp = ms_to_samples(ramp_ms, st.sr);
+        schedule(&mut st, when_samples, Cmd::TrackGain { track: track_id.to_string(), gain: db_to_lin(gain_db), ramp });
+        true
+    })
+}
+
+/// Ramps a track's equal-power pan position (`-1..=1`) over `ramp_ms` (0 = immediate).
+pub fn engine_set_track_pan(track_id: &str, pan: f32, ramp_ms: f32) -> bool {
+    engine_set_track_pan_at(track_id, 0, pan, ramp_ms)
+}
+
+pub fn engine_set_track_pan_at(track_id: &str, when_samples: u64, pan: f32, ramp_ms: f32) -> bool {
+    AUDIO.with(|a| {
+        let mut st = a.borrow_mut();
+        if !st.track_index.contains_key(track_id) { return false; }
+        let ramp = ms_to_samples(ramp_ms, st.sr);
+        schedule(&mut st, when_samples, Cmd::TrackPan { track: track_id.to_string(), pan: pan.clamp(-1.0, 1.0), ramp });
+        true
+    })
+}
+
+/// Stops a track at engine sample `when_samples` (anything in the past means now), fading out
+/// over `fade_out_ms`. The track is removed from the engine once the fade has finished.
+pub fn engine_stop(track_id: &str, when_samples: u64, fade_out_ms: f32) -> bool {
+    AUDIO.with(|a| {
+        let mut st = a.borrow_mut();
+        if !st.track_index.contains_key(track_id) { return false; }
+        let fade = ms_to_samples(fade_out_ms, st.sr);
+        schedule(&mut st, when_samples, Cmd::Stop { track: track_id.to_string(), fade });
+        true
     })
 }
 
+/// Peak-detecting ducker of `target_bus` keyed by `key_bus`, without hold.
+#[allow(clippy::too_many_arguments)]
 pub fn engine_set_ducker(target_bus: &str, key_bus: &str, threshold_db: f32, ratio: f32, attack_ms: f32, release_ms: f32, max_atten_db: f32, makeup_db: f32) {
+    engine_set_ducker_keys(target_bus, &[key_bus], threshold_db, ratio, attack_ms, release_ms, 0.0, max_atten_db, makeup_db, "peak", 0.0, "max");
+}
+
+/// Ducks `target_bus` by the level of `key_buses`, replacing a ducker with the same target and
+/// key set; duckers with other keys on the same target stay and their reductions multiply.
+/// `detector` is `peak` or `rms` (over `rms_window_ms`), `combine` is `max` or `sum` (energy
+/// sum of the key levels). The reduction is held for `hold_ms` after the key falls below the
+/// threshold before it releases.
+#[allow(clippy::too_many_arguments)]
+pub fn engine_set_ducker_keys(target_bus: &str, key_buses: &[&str], threshold_db: f32, ratio: f32, attack_ms: f32, release_ms: f32, hold_ms: f32, max_atten_db: f32, makeup_db: f32, detector: &str, rms_window_ms: f32, combine: &str) -> bool {
+    let combine = match combine {
+        "max" | "" => KeyCombine::Max,
+        "sum" => KeyCombine::EnergySum,
+        _ => return false,
+    };
+    let rms = match detector {
+        "peak" | "" => false,
+        "rms" => true,
+        _ => return false,
+    };
+    if key_buses.is_empty() { return false; }
     AUDIO.with(|a| {
         let mut st = a.borrow_mut();
         let atk = 1.0 - (-1.0f32 / (st.sr * attack_ms / 1000.0)).exp();
         let rel = 1.0 - (-1.0f32 / (st.sr * release_ms / 1000.0)).exp();
+        let window = if rms { ms_to_samples(rms_window_ms, st.sr).max(1) } else { 0 };
+        // Duckers refer to bus slots, so buses they name are created on demand like track buses
+        let mut keys: Vec<KeyDetector> = key_buses.iter().map(|k| KeyDetector { bus: st.bus_slot(k), ring: vec![0.0; window], pos: 0, sum: 0.0 }).collect();
+        keys.sort_by_key(|k| k.bus);
+        keys.dedup_by_key(|k| k.bus);
         let ducker = Ducker {
-            target_bus: target_bus.to_string(),
-            key_bus: key_bus.to_string(),
+            target_bus: st.bus_slot(target_bus),
+            keys,
+            combine,
             threshold_db,
             threshold_lin: 10.0f32.powf(threshold_db / 20.0),
             ratio: if ratio < 1.0 { 1.0 } else { ratio },
             attack: atk,
             release: rel,
+            hold: ms_to_samples(hold_ms, st.sr),
+            hold_left: 0,
             max_atten_db: if max_atten_db < 0.0 { 0.0 } else { max_atten_db },
             makeup_lin: 10.0f32.powf(makeup_db / 20.0),
             env: 0.0,
             gr: 1.0,
         };
-        st.duckers.retain(|d| d.target_bus != ducker.target_bus);
+        st.duckers.retain(|d| !(d.target_bus == ducker.target_bus && same_keys(d, &ducker)));
         st.duckers.push(ducker);
+        true
+    })
+}
+
+fn same_keys(a: &Ducker, b: &Ducker) -> bool {
+    a.keys.len() == b.keys.len() && a.keys.iter().zip(&b.keys).all(|(x, y)| x.bus == y.bus)
+}
+
+/// Removes the ducker of `target_bus` keyed by exactly `key_buses`, or every ducker of the
+/// target when `key_buses` is empty. Returns whether anything was removed.
+pub fn engine_remove_ducker(target_bus: &str, key_buses: &[&str]) -> bool {
+    AUDIO.with(|a| {
+        let mut st = a.borrow_mut();
+        let target = match st.bus_index.get(target_bus) { Some(&i) => i, None => return false };
+        let mut keys = Vec::with_capacity(key_buses.len());
+        for k in key_buses {
+            match st.bus_index.get(*k) { Some(&i) => keys.push(i), None => return false }
+        }
+        keys.sort_unstable();
+        keys.dedup();
+        let before = st.duckers.len();
+        st.duckers.retain(|d| !(d.target_bus == target && (keys.is_empty() || d.keys.iter().map(|k| k.bus).eq(keys.iter().copied()))));
+        st.duckers.len() != before
     })
 }
 
-fn sample_pair(asset: &AudioAsset, idx: usize, frac: f64) -> (f32, f32) {
-    let ch0 = &asset.ch[0];
-    let ch1 = if asset.ch.len() > 1 { &asset.ch[1] } else { &asset.ch[0] };
-    let i0 = idx;
-    let i1 = idx + 1;
-    let s0l = ch0.get(i0).copied().unwrap_or(0.0);
-    let s1l = ch0.get(i1).copied().unwrap_or(0.0);
-    let s0r = ch1.get(i0).copied().unwrap_or(0.0);
-    let s1r = ch1.get(i1).copied().unwrap_or(0.0);
-    let l = (s1l - s0l) as f64 * frac + s0l as f64;
-    let r = (s1r - s0r) as f64 * frac + s0r as f64;
-    (l as f32, r as f32)
+/// Runtime interpolator used to read assets at fractional positions.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum Interp {
+    Nearest,
+    #[default]
+    Linear,
+    Hermite, // 4-point, 3rd-order (Catmull-Rom)
+    Sinc,    // 8-tap Blackman-windowed sinc
+}
+
+impl Interp {
+    fn parse(s: &str) -> Option<Interp> {
+        match s {
+            "nearest" => Some(Interp::Nearest),
+            "linear" => Some(Interp::Linear),
+            "hermite" | "cubic" => Some(Interp::Hermite),
+            "sinc" => Some(Interp::Sinc),
+            _ => None,
+        }
+    }
+}
+
+/// Sinc kernel taps cover frames `idx - 3 ..= idx + 4`.
+const SINC_TAPS: usize = 8;
+/// Fractional positions are rounded to this many kernel phases.
+const SINC_PHASES: usize = 256;
+
+/// `SINC_PHASES + 1` rows of `SINC_TAPS` weights, each row normalised to unity DC gain.
+fn sinc_table() -> Vec<f32> {
+    use std::f64::consts::PI;
+    let half = (SINC_TAPS / 2) as f64;
+    let mut table = Vec::with_capacity((SINC_PHASES + 1) * SINC_TAPS);
+    for p in 0..=SINC_PHASES {
+        let frac = p as f64 / SINC_PHASES as f64;
+        let row: Vec<f64> = (0..SINC_TAPS)
+            .map(|t| {
+                let d = t as f64 - (half - 1.0) - frac;
+                let u = d / half;
+                let w = if u.abs() >= 1.0 { 0.0 } else { 0.42 + 0.5 * (PI * u).cos() + 0.08 * (2.0 * PI * u).cos() };
+                let sinc = if d.abs() < 1e-12 { 1.0 } else { (PI * d).sin() / (PI * d) };
+                sinc * w
+            })
+            .collect();
+        let sum: f64 = row.iter().sum();
+        table.extend(row.iter().map(|w| (w / sum) as f32));
+    }
+    table
+}
+
+/// Interpolates between frames `0` and `1` of `tap` (indices relative to the playhead) at `frac`.
+fn interpolate(interp: Interp, sinc: &[f32], frac: f64, tap: impl Fn(isize) -> (f32, f32)) -> (f32, f32) {
+    match interp {
+        Interp::Nearest => if frac < 0.5 { tap(0) } else { tap(1) },
+        Interp::Linear => {
+            let ((s0l, s0r), (s1l, s1r)) = (tap(0), tap(1));
+            (((s1l as f64 - s0l as f64) * frac + s0l as f64) as f32, ((s1r as f64 - s0r as f64) * frac + s0r as f64) as f32)
+        }
+        Interp::Hermite => {
+            let (xm, x0, x1, x2) = (tap(-1), tap(0), tap(1), tap(2));
+            let f = frac as f32;
+            let h = |xm: f32, x0: f32, x1: f32, x2: f32| {
+                let c1 = 0.5 * (x1 - xm);
+                let c2 = xm - 2.5 * x0 + 2.0 * x1 - 0.5 * x2;
+                let c3 = 0.5 * (x2 - xm) + 1.5 * (x0 - x1);
+                ((c3 * f + c2) * f + c1) * f + x0
+            };
+            (h(xm.0, x0.0, x1.0, x2.0), h(xm.1, x0.1, x1.1, x2.1))
+        }
+        Interp::Sinc => {
+            let phase = ((frac * SINC_PHASES as f64).round() as usize).min(SINC_PHASES);
+            let row = &sinc[phase * SINC_TAPS..(phase + 1) * SINC_TAPS];
+            let mut acc = (0.0f32, 0.0f32);
+            for (t, w) in row.iter().enumerate() {
+                let (l, r) = tap(t as isize - (SINC_TAPS as isize / 2 - 1));
+                acc.0 += l * w;
+                acc.1 += r * w;
+            }
+            acc
+        }
+    }
+}
+
+/// Frame `j` of `asset`, silent before the start.
+fn frame_at(asset: &AudioAsset, j: isize) -> (f32, f32) {
+    if j < 0 { (0.0, 0.0) } else { frame(asset, j as usize) }
+}
+
+/// Frame `j` on the track's playback timeline rather than in its asset: past a pending
+/// transition point it reads the incoming asset, past the loop end it continues from where
+/// the wrap resumes, and before the start of a loop that has already wrapped it reads the
+/// loop tail. This keeps interpolation kernels continuous across seams.
+fn timeline_frame(tr: &AudioTrack, asset: &AudioAsset, j: isize, lstart: usize, lend: usize) -> (f32, f32) {
+    let looping = tr.loop_cfg.mode != LoopMode::None;
+    if let Some((to, lc)) = &tr.pending_switch {
+        let at = match tr.pending_switch_at { Some(at) => Some(at), None if looping => Some(lend), None => None };
+        if let Some(at) = at.filter(|&at| j >= at as isize) {
+            return frame(to, lc.start + (j - at as isize) as usize);
+        }
+    }
+    if looping && lend > lstart {
+        let len = (lend - lstart) as isize;
+        if j >= lend as isize {
+            let resume = if tr.loop_cfg.mode == LoopMode::Xfade { tr.loop_cfg.xfade as isize } else { 0 };
+            return frame(asset, lstart + ((j - lend as isize + resume) % len) as usize);
+        }
+        if j < lstart as isize && tr.loop_count > 0 {
+            return frame_at(asset, lend as isize - (lstart as isize - j) % len);
+        }
+    }
+    frame_at(asset, j)
 }
 
+/// Renders one block. Timeline commands falling inside the block split it, so each command
+/// takes effect exactly at its sample.
 pub fn engine_process_into(out_l: &mut [f32], out_r: &mut [f32]) -> u32 {
     let n = out_l.len().min(out_r.len());
-    for i in 0..n { out_l[i] = 0.0; out_r[i] = 0.0; }
     AUDIO.with(|a| {
         let mut st = a.borrow_mut();
-        use std::collections::HashMap;
-        let mut bus_acc: HashMap<String, (Vec<f32>, Vec<f32>)> = HashMap::new();
-        let ids: Vec<String> = st.tracks.keys().cloned().collect();
-        for tid in ids {
-            let mut tr = match st.tracks.get(&tid).cloned() { Some(t) => t, None => continue };
-            if !tr.playing { continue; }
-            let mut asset_id = tr.asset_id.clone();
-            let mut asset = match st.assets.get(&asset_id) { Some(x) => x.clone(), None => continue };
+        let st = &mut *st;
+        let mut off = 0;
+        while off < n {
+            while st.timeline.front().is_some_and(|e| e.when <= st.current_sample) {
+                if let Some(e) = st.timeline.pop_front() { apply_cmd(st, e.cmd); }
+            }
+            let end = match st.timeline.front() {
+                Some(e) => n.min(off + (e.when - st.current_sample) as usize),
+                None => n,
+            }
+            .min(off + MAX_BLOCK);
+            render(st, &mut out_l[off..end], &mut out_r[off..end]);
+            off = end;
+        }
+        if !st.retired.is_empty() { st.release_retired(); }
+    });
+    n as u32
+}
+
+/// Frame `idx` of `asset` as `(left, right)`; mono assets feed both sides, reads past the end are silent.
+fn frame(asset: &AudioAsset, idx: usize) -> (f32, f32) {
+    let l = asset.ch[0].get(idx).copied().unwrap_or(0.0);
+    let r = match asset.ch.get(1) { Some(ch) => ch.get(idx).copied().unwrap_or(l), None => l };
+    (l, r)
+}
+
+fn render(st: &mut AudioState, out_l: &mut [f32], out_r: &mut [f32]) {
+    let n = out_l.len();
+    out_l.fill(0.0);
+    out_r.fill(0.0);
+    let engine_sr = st.sr;
+    let meter_window = st.meter_window;
+    let AudioState { tracks, track_index, buses, duckers, events, master, .. } = st;
+    let ctx = RenderCtx { now: st.current_sample, engine_sr, interp: st.interp, sinc: &st.sinc };
+    for b in buses.iter_mut() {
+        b.acc_l[..n].fill(0.0);
+        b.acc_r[..n].fill(0.0);
+    }
+    for si in 0..tracks.len() {
+        let tr = match tracks[si].as_mut() { Some(t) if t.playing && t.rendered_at != ctx.now => t, _ => continue };
+        tr.rendered_at = ctx.now;
+        let bus = tr.bus;
+        let b = &mut buses[bus];
+        let mut ended = render_track(tr, &mut b.acc_l[..n], &mut b.acc_r[..n], events, &ctx);
+        let mut slot = si;
+        // Each voice that ends hands its place, from that very sample, to the next queued play
+        while let Some(off) = ended {
+            if tracks[slot].as_ref().is_some_and(|t| t.stopping) {
+                if let Some(t) = tracks[slot].take() { track_index.remove(&*t.id); }
+            }
+            ended = None;
+            while let Some(q) = buses[bus].queue.pop_front() {
+                let next = match track_index.get(&*q.track) { Some(&i) => i, None => continue };
+                let tr = match tracks[next].as_mut() { Some(t) => t, None => continue };
+                let at = ctx.now + off as u64;
+                tr.start(q.offset, q.loop_cfg, engine_sr, events, at);
+                tr.rendered_at = ctx.now;
+                let b = &mut buses[bus];
+                let sub = RenderCtx { now: at, ..ctx };
+                ended = render_track(tr, &mut b.acc_l[off..n], &mut b.acc_r[off..n], events, &sub).map(|k| off + k);
+                slot = next;
+                break;
+            }
+        }
+    }
+    // Apply duckers
+    for d in duckers.iter_mut() {
+        let mut env = d.env;
+        let mut gr = d.gr;
+        let mut hold_left = d.hold_left;
+        for i in 0..n {
+            let mut level = 0.0f32;
+            for k in d.keys.iter_mut() {
+                let key = &buses[k.bus];
+                let x = k.level(0.5 * (key.acc_l[i] * key.acc_l[i] + key.acc_r[i] * key.acc_r[i]));
+                level = match d.combine { KeyCombine::Max => level.max(x), KeyCombine::EnergySum => level + x * x };
+            }
+            let mag = if d.combine == KeyCombine::EnergySum { level.sqrt() } else { level };
+            let delta = mag - env;
+            env += if delta > 0.0 { d.attack } else { d.release } * delta;
+            let mut gtar = 1.0;
+            if env > d.threshold_lin {
+                let env_db = 20.0 * (env + 1e-12).log10();
+                let exceed = env_db - d.threshold_db;
+                let atten_db = (1.0 - 1.0 / d.ratio) * exceed;
+                let gdb = -atten_db.clamp(0.0, d.max_atten_db);
+                gtar = 10.0_f32.powf(gdb / 20.0);
+            }
+            // Hold: no release until the key has stayed below threshold for `hold` samples
+            let active = env > d.threshold_lin;
+            if active { hold_left = d.hold; }
+            let dgr = gtar - gr;
+            if dgr <= 0.0 {
+                gr += d.attack * dgr;
+            } else if hold_left > 0 {
+                if !active { hold_left -= 1; }
+            } else {
+                gr += d.release * dgr;
+            }
+            let target = &mut buses[d.target_bus];
+            target.acc_l[i] *= gr * d.makeup_lin;
+            target.acc_r[i] *= gr * d.makeup_lin;
+        }
+        d.env = env;
+        d.gr = gr;
+        d.hold_left = hold_left;
+    }
+    // Bus gain, mute/solo gate and low-pass, then mix buses to master
+    let any_solo = buses.iter().any(|b| b.solo);
+    let gate_len = ms_to_samples(BUS_GATE_MS, engine_sr);
+    for b in buses.iter_mut() {
+        let open = !b.mute && (!any_solo || b.solo);
+        let gate = if open { 1.0 } else { 0.0 };
+        if b.gate.target != gate { b.gate.set_target(gate, gate_len); }
+        for i in 0..n {
+            let g = b.gain.step() * b.gate.step();
+            let (l, r) = b.lpf.process(b.acc_l[i] * g, b.acc_r[i] * g, engine_sr);
+            b.meter.add(l, r, meter_window);
+            out_l[i] += l;
+            out_r[i] += r;
+        }
+    }
+    // Master: headroom and gain, then the look-ahead limiter
+    for i in 0..n {
+        let g = master.headroom * master.gain.step();
+        let (l, r) = master.limiter.process(out_l[i] * g, out_r[i] * g);
+        master.meter.add(l, r, meter_window);
+        master.true_peak.add(l, r, meter_window);
+        out_l[i] = l;
+        out_r[i] = r;
+    }
+    st.current_sample += n as u64;
+}
+
+/// Per-block values shared by every track render.
+#[derive(Clone, Copy)]
+struct RenderCtx<'a> {
+    now: u64,
+    engine_sr: f32,
+    interp: Interp,
+    sinc: &'a [f32],
+}
+
+/// Mixes one playing track into its bus accumulators. Returns the offset at which it stopped
+/// playing, if it did; a track that was stopping is then due for removal.
+fn render_track(tr: &mut AudioTrack, acc_l: &mut [f32], acc_r: &mut [f32], events: &mut Vec<EngineEvent>, ctx: &RenderCtx) -> Option<usize> {
+    let (now, engine_sr) = (ctx.now, ctx.engine_sr);
+    // Shared handle: switching assets below never copies PCM
+    let mut asset = tr.asset.clone();
+    let mut pos = tr.pos;
+    for i in 0..acc_l.len() {
+        let at_samples = now + i as u64;
+        let fade = tr.fade.step();
+        if tr.stopping && !tr.fade.is_ramping() {
+            tr.playing = false;
+            tr.pos = pos;
+            push_event(events, EngineEvent::TrackEnded { track_id: tr.id.clone(), at_samples });
+            return Some(i);
+        }
+        let mut jumped = false;
+        // A crossfaded switch starts ahead of its boundary: the switch point or the loop end,
+        // whichever comes first
+        if let (Some(f), Some(_)) = (tr.pending_fade, &tr.pending_switch) {
             let len_src = asset.ch[0].len();
-            let (pl, pr) = (tr.pan_l, tr.pan_r);
-            let mut pos = tr.pos;
-            let step = tr.step;
-            let acc = bus_acc.entry(tr.bus.clone()).or_insert_with(|| (vec![0.0; n], vec![0.0; n]));
-            for i in 0..n {
-                let mut idx = pos.floor() as usize;
-                let mut frac = (pos - idx as f64) as f64;
-                if let Some(at) = tr.pending_switch_at {
-                    if idx >= at {
-                        if let Some((to_id, lc)) = tr.pending_switch.clone() {
-                            if let Some(new_asset) = st.assets.get(&to_id).cloned() {
-                                asset_id = to_id.clone();
-                                asset = new_asset;
-                                tr.asset_id = to_id;
-                                tr.loop_cfg = lc;
-                                pos = tr.loop_cfg.start as f64;
-                                idx = pos.floor() as usize;
-                                frac = pos - idx as f64;
-                            }
-                        }
-                        tr.pending_switch = None;
-                        tr.pending_switch_at = None;
-                    }
-                }
-                // Handle loop boundary for position/asset state (no gap)
-                match tr.loop_cfg.mode {
-                    LoopMode::Seamless => {
-                        let lstart = tr.loop_cfg.start;
-                        let lend = tr.loop_cfg.end.unwrap_or(len_src);
-                        if idx >= lend {
-                            if let Some((to_id, lc)) = tr.pending_switch.clone() {
-                                if let Some(new_asset) = st.assets.get(&to_id).cloned() {
-                                    asset_id = to_id.clone();
-                                    asset = new_asset;
-                                    tr.asset_id = to_id;
-                                    tr.loop_cfg = lc;
-                                    pos = tr.loop_cfg.start as f64;
-                                }
-                                tr.pending_switch = None;
-                            } else {
-                                let over = pos - lend as f64;
-                                pos = lstart as f64 + over;
-                            }
-                            idx = pos.floor() as usize;
-                            frac = pos - idx as f64;
-                        }
-                    }
-                    LoopMode::Xfade => {
-                        let lstart = tr.loop_cfg.start;
-                        let lend = tr.loop_cfg.end.unwrap_or(len_src);
-                        if idx >= lend {
-                            if let Some((to_id, lc)) = tr.pending_switch.clone() {
-                                if let Some(new_asset) = st.assets.get(&to_id).cloned() {
-                                    asset_id = to_id.clone();
-                                    asset = new_asset;
-                                    tr.asset_id = to_id;
-                                    tr.loop_cfg = lc;
-                                    pos = tr.loop_cfg.start as f64;
-                                }
-                                tr.pending_switch = None;
-                            } else {
-                                let over = pos - lend as f64;
-                                pos = lstart as f64 + over;
-                            }
-                            idx = pos.floor() as usize;
-                            frac = pos - idx as f64;
-                        }
-                    }
-                    LoopMode::None => {}
-                }
-                // Sample-accurate interpolation with boundary-aware next sample
-                if idx >= asset.ch[0].len().saturating_sub(1) && tr.loop_cfg.mode == LoopMode::None {
-                    tr.playing = false; break;
+            let lend = (tr.loop_cfg.mode != LoopMode::None).then(|| tr.loop_cfg.end.unwrap_or(len_src).min(len_src));
+            let boundary = match (tr.pending_switch_at, lend) {
+                (Some(at), Some(end)) => Some(at.min(end)),
+                (at, end) => at.or(end),
+            };
+            let lead = match f.align { FadeAlign::Before => f.len, FadeAlign::Center => f.len / 2 } as f64 * tr.step;
+            if let Some(b) = boundary.filter(|&b| pos >= b as f64 - lead) {
+                if let Some((to, lc)) = tr.pending_switch.take() {
+                    let ahead = (b as f64 - pos).max(0.0) / tr.step; // engine samples to the boundary
+                    tr.pos = pos;
+                    tr.crossfade_to(to, lc, f.len, engine_sr);
+                    // Pre-roll the incoming asset so its loop start still lands on the boundary
+                    tr.pos -= ahead * tr.step;
+                    asset = tr.asset.clone();
+                    pos = tr.pos;
+                    jumped = true;
                 }
-                // current sample
-                let ch0 = &asset.ch[0];
-                let ch1 = if asset.ch.len() > 1 { &asset.ch[1] } else { &asset.ch[0] };
-                let s0l = *ch0.get(idx).unwrap_or(&0.0);
-                let s0r = *ch1.get(idx).unwrap_or(&s0l);
-                // next sample (for interpolation)
-                let mut s1l;
-                let mut s1r;
-                let idx1 = idx + 1;
-                let lend = tr.loop_cfg.end.unwrap_or(len_src);
-                let lstart = tr.loop_cfg.start;
-                let pending_to = tr.pending_switch.clone();
-                if tr.loop_cfg.mode == LoopMode::Seamless && idx1 == lend {
-                    // wrap to loop start
-                    s1l = *ch0.get(lstart).unwrap_or(&0.0);
-                    let ch1r = if asset.ch.len() > 1 { &asset.ch[1] } else { &asset.ch[0] };
-                    s1r = *ch1r.get(lstart).unwrap_or(&s1l);
-                } else if let Some((ref to_id, ref lc)) = pending_to {
-                    if idx1 >= lend {
-                        // transition at loopEnd: step into new asset at its start
-                        if let Some(new_asset) = st.assets.get(to_id).cloned() {
-                            let n_ch0 = &new_asset.ch[0];
-                            let n_ch1 = if new_asset.ch.len() > 1 { &new_asset.ch[1] } else { &new_asset.ch[0] };
-                            let start = lc.start;
-                            s1l = *n_ch0.get(start).unwrap_or(&0.0);
-                            s1r = *n_ch1.get(start).unwrap_or(&s1l);
-                        } else {
-                            s1l = *ch0.get(idx1.min(len_src-1)).unwrap_or(&0.0);
-                            s1r = *ch1.get(idx1.min(len_src-1)).unwrap_or(&s1l);
-                        }
-                    } else {
-                        s1l = *ch0.get(idx1).unwrap_or(&0.0);
-                        s1r = *ch1.get(idx1).unwrap_or(&s1l);
-                    }
+            }
+        }
+        if tr.pending_switch_at.is_some_and(|at| pos.floor() as usize >= at) {
+            tr.pending_switch_at = None;
+            if let Some((to, lc)) = tr.pending_switch.take() {
+                tr.switch_asset(to, lc, engine_sr, events, at_samples);
+                asset = tr.asset.clone();
+                pos = tr.pos;
+                jumped = true;
+            }
+        }
+        // Handle loop boundary for position/asset state (no gap)
+        if tr.loop_cfg.mode != LoopMode::None {
+            let len_src = asset.ch[0].len();
+            let lend = tr.loop_cfg.end.unwrap_or(len_src).min(len_src);
+            if pos.floor() as usize >= lend {
+                if let Some((to, lc)) = tr.pending_switch.take() {
+                    tr.switch_asset(to, lc, engine_sr, events, at_samples);
+                    asset = tr.asset.clone();
+                    pos = tr.pos;
                 } else {
-                    s1l = *ch0.get(idx1.min(len_src-1)).unwrap_or(&0.0);
-                    s1r = *ch1.get(idx1.min(len_src-1)).unwrap_or(&s1l);
+                    // Crossfade loops resume after `start + xfade`: that head was already heard inside the crossfade window
+                    let head = if tr.loop_cfg.mode == LoopMode::Xfade { tr.loop_cfg.xfade } else { 0 };
+                    pos = (tr.loop_cfg.start + head) as f64 + (pos - lend as f64);
+                    tr.loop_count += 1;
+                    push_event(events, EngineEvent::TrackLooped { track_id: tr.id.clone(), count: tr.loop_count, at_samples });
                 }
-                let sl = ((s1l as f64 - s0l as f64) * frac + s0l as f64) as f32;
-                let sr_ = ((s1r as f64 - s0r as f64) * frac + s0r as f64) as f32;
-                let g = tr.gain;
-                acc.0[i] += sl * g * pl;
-                acc.1[i] += sr_ * g * pr;
-                pos += step;
+                jumped = true;
             }
-            tr.pos = pos;
-            st.tracks.insert(tid.clone(), tr);
-        }
-        // Apply duckers
-        for d in st.duckers.iter_mut() {
-            // Borrow order: get key bus snapshot first (immutable, cloned),
-            // then take mutable borrow of target bus to avoid E0502.
-            let (k_l_vec, k_r_vec) = match bus_acc.get(&d.key_bus) {
-                Some((l, r)) => (l.clone(), r.clone()),
-                None => continue,
-            };
-            let (t_l, t_r) = match bus_acc.get_mut(&d.target_bus) {
-                Some((l, r)) => (l, r),
-                None => continue,
-            };
-            let n = t_l.len().min(k_l_vec.len());
-            let mut env = d.env;
-            let mut gr = d.gr;
-            for i in 0..n {
-                let mag = ((k_l_vec[i] * k_l_vec[i] + k_r_vec[i] * k_r_vec[i]).sqrt()) * 0.7071;
-                let delta = mag - env;
-                env += if delta > 0.0 { d.attack } else { d.release } * delta;
-                let mut gtar = 1.0;
-                if env > d.threshold_lin {
-                    let env_db = 20.0 * (env + 1e-12).log10();
-                    let exceed = env_db - d.threshold_db;
-                    let atten_db = (1.0 - 1.0 / d.ratio) * exceed;
-                    let gdb = -atten_db.clamp(0.0, d.max_atten_db);
-                    gtar = 10.0_f32.powf(gdb / 20.0);
+        }
+        let interp = tr.interp.unwrap_or(ctx.interp);
+        // Before the incoming asset's first sample (pre-roll of a crossfaded switch) it is silent
+        let (mut sl, mut sr_) = if pos < 0.0 {
+            (0.0, 0.0)
+        } else {
+            let idx = pos.floor() as usize;
+            let frac = pos - idx as f64;
+            let len_src = asset.ch[0].len();
+            if idx >= len_src.saturating_sub(1) && tr.loop_cfg.mode == LoopMode::None {
+                tr.playing = false;
+                tr.pos = pos;
+                push_event(events, EngineEvent::TrackEnded { track_id: tr.id.clone(), at_samples });
+                return Some(i);
+            }
+            if jumped { tr.marker_cursor = tr.markers.partition_point(|&(m, _)| m < idx); }
+            while let Some(&(m, marker)) = tr.markers.get(tr.marker_cursor) {
+                if m > idx { break; }
+                push_event(events, EngineEvent::MarkerReached { track_id: tr.id.clone(), marker, at_samples });
+                tr.marker_cursor += 1;
+            }
+            // Sample-accurate interpolation; kernel taps are looked up on the playback timeline
+            let lend = tr.loop_cfg.end.unwrap_or(len_src).min(len_src);
+            let lstart = tr.loop_cfg.start;
+            let (mut sl, mut sr_) = interpolate(interp, ctx.sinc, frac, |k| timeline_frame(tr, &asset, idx as isize + k, lstart, lend));
+            // Crossfade loop: overlap the tail before loop end with the head from loop start
+            let xf = tr.loop_cfg.xfade.min(lend.saturating_sub(lstart) / 2);
+            if tr.loop_cfg.mode == LoopMode::Xfade && xf > 0 && tr.pending_switch.is_none() {
+                let win_start = lend - xf;
+                if idx >= win_start && idx < lend {
+                    let (g_out, g_in) = equal_power((pos - win_start as f64) / xf as f64);
+                    let head = (lstart + (idx - win_start)) as isize;
+                    let (hl, hr) = interpolate(interp, ctx.sinc, frac, |k| frame_at(&asset, head + k));
+                    sl = sl * g_out + hl * g_in;
+                    sr_ = sr_ * g_out + hr * g_in;
                 }
-                let dgr = gtar - gr;
-                gr += if dgr > 0.0 { d.release } else { d.attack } * dgr;
-                t_l[i] *= gr * d.makeup_lin;
-                t_r[i] *= gr * d.makeup_lin;
             }
-            d.env = env;
-            d.gr = gr;
+            (sl, sr_)
+        };
+        // Crossfaded switch: the outgoing asset plays out under the incoming one, with the pan
+        // law of its own channel count
+        if tr.tail.as_ref().is_some_and(|t| t.done >= t.len) {
+            tr.tail = None;
+            push_event(events, EngineEvent::TransitionCompleted { track_id: tr.id.clone(), asset_id: asset.id.clone(), at_samples });
         }
-        // Mix buses to master
-        for (_id, (bl, br)) in bus_acc.into_iter() {
-            for i in 0..n { out_l[i] += bl[i]; out_r[i] += br[i]; }
+        if let Some(tail) = tr.tail.as_mut() {
+            let (g_out, g_in) = equal_power(tail.done as f64 / tail.len as f64);
+            let (tl, tr_) = tail.next(interp, ctx.sinc);
+            let (tpl, tpr) = pan_coeffs(tr.pan.value, tail.asset.ch.len() > 1);
+            let rel = |p: f32, q: f32| if q > 0.0 { p / q } else { 0.0 };
+            sl = sl * g_in + tl * g_out * rel(tpl, tr.pan_l);
+            sr_ = sr_ * g_in + tr_ * g_out * rel(tpr, tr.pan_r);
         }
-    });
-    n as u32
+        let (sl, sr_) = tr.lpf.process(sl, sr_, engine_sr);
+        let g = tr.gain.step() * fade;
+        if tr.pan.is_ramping() {
+            let (pl, pr) = pan_coeffs(tr.pan.step(), asset.ch.len() > 1);
+            tr.pan_l = pl;
+            tr.pan_r = pr;
+        }
+        acc_l[i] += sl * g * tr.pan_l;
+        acc_r[i] += sr_ * g * tr.pan_r;
+        pos += tr.step;
+    }
+    tr.pos = pos;
+    None
+}
+
+// ----- JSON command entry point (same message schema as the worklet) -----
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LoopSpec {
+    mode: String,
+    #[serde(default)]
+    start: u32,
+    end: Option<i64>,
+    #[serde(default)]
+    crossfade_ms: u32,
+}
+
+impl LoopSpec {
+    /// `(mode, start, end, xfade_ms)` in the positional form the engine functions take; a
+    /// missing loop means one-shot playback.
+    fn parts(spec: &Option<LoopSpec>) -> (&str, u32, i32, u32) {
+        match spec {
+            Some(l) => (l.mode.as_str(), l.start, l.end.map_or(-1, |e| e.clamp(-1, i32::MAX as i64) as i32), l.crossfade_ms),
+            None => ("none", 0, -1, 0),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct LpfSpec {
+    #[serde(default)]
+    enabled: bool,
+    #[serde(default)]
+    cutoff_hz: f32,
+    q: Option<f32>,
+    order: Option<u8>,
+}
+
+#[derive(serde::Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct BusOptions {
+    gain_db: Option<f32>,
+    lpf: Option<LpfSpec>,
+}
+
+#[derive(serde::Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct TrackOptions {
+    #[serde(default)]
+    gain_db: f32,
+    #[serde(default)]
+    pan: f32,
+}
+
+#[derive(serde::Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct DuckerParams {
+    threshold_db: Option<f32>,
+    ratio: Option<f32>,
+    attack_ms: Option<f32>,
+    release_ms: Option<f32>,
+    hold_ms: Option<f32>,
+    max_atten_db: Option<f32>,
+    makeup_db: Option<f32>,
+    detector: Option<String>,
+    rms_window_ms: Option<f32>,
+    key_combine: Option<String>,
+}
+
+/// Key buses of a ducker command, from `keyBusId` and/or `keyBusIds`.
+fn key_ids<'a>(one: &'a Option<String>, many: &'a Option<Vec<String>>) -> Vec<&'a str> {
+    one.iter().chain(many.iter().flatten()).map(String::as_str).collect()
+}
+
+#[derive(serde::Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase", rename_all_fields = "camelCase")]
+enum EngineCommand {
+    CreateBus { bus_id: String, #[serde(default)] options: BusOptions },
+    CreateTrack { track_id: String, bus_id: String, asset_id: String, #[serde(default)] options: TrackOptions },
+    SchedulePlay { track_id: String, when_samples: Option<f64>, #[serde(default)] offset_samples: u32, #[serde(rename = "loop")] loop_spec: Option<LoopSpec> },
+    Stop { track_id: String, when_samples: Option<f64>, #[serde(default)] fade_out_ms: f32 },
+    SetGain { scope: String, id: Option<String>, when_samples: Option<f64>, #[serde(default)] gain_db: f32, #[serde(default)] ramp_ms: f32 },
+    #[serde(rename = "setLPF")]
+    SetLpf { scope: String, id: String, cutoff_hz: f32, q: Option<f32>, #[serde(default)] ramp_ms: f32, order: Option<u8> },
+    SetDucker { target_bus_id: String, key_bus_id: Option<String>, key_bus_ids: Option<Vec<String>>, #[serde(default)] params: DuckerParams },
+    RemoveDucker { target_bus_id: String, key_bus_id: Option<String>, key_bus_ids: Option<Vec<String>> },
+    SetMarkers { track_id: String, markers_samples: Vec<usize>, bpm: Option<f64>, time_sig: Option<[u32; 2]> },
+    SetTempo { track_id: String, bpm: f64, time_sig: Option<[u32; 2]>, #[serde(default)] downbeat_samples: f64 },
+    SetInterpolation { track_id: Option<String>, mode: String },
+    SetPolyphony { bus_id: String, max_voices: u32, #[serde(default)] policy: String },
+    SetLoop { track_id: String, when_samples: Option<f64>, #[serde(rename = "loop")] loop_spec: Option<LoopSpec> },
+    Transition { track_id: String, when_samples: Option<f64>, #[serde(default = "default_transition_at")] at: AtSpec, to_asset_id: String, #[serde(rename = "loop")] loop_spec: Option<LoopSpec>, mode: Option<SwitchMode> },
+    CreateGroup { group_id: String, track_ids: Vec<String> },
+    SetGroupMarkers { group_id: String, markers_samples: Vec<usize> },
+    GroupPlay { group_id: String, when_samples: Option<f64>, #[serde(default)] offset_samples: u32, #[serde(rename = "loop")] loop_spec: Option<LoopSpec> },
+    GroupStop { group_id: String, when_samples: Option<f64>, #[serde(default)] fade_out_ms: f32 },
+    GroupTransition { group_id: String, when_samples: Option<f64>, #[serde(default = "default_transition_at")] at: AtSpec, to_asset_ids: Vec<String>, #[serde(rename = "loop")] loop_spec: Option<LoopSpec>, mode: Option<SwitchMode> },
+    SetStemGains { group_id: String, when_samples: Option<f64>, gains_db: HashMap<String, f32>, #[serde(default)] ramp_ms: f32 },
+    UnloadBuffer { buffer_id: String, defer: Option<bool> },
+    SetMemoryBudget { bytes: f64 },
+}
+
+/// Transition point: a name (`now`, `nextBar`, ...) or `{ "bar": N }`.
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum AtSpec {
+    Named(String),
+    Bar { bar: u32 },
+}
+
+impl AtSpec {
+    /// The form `engine_transition_at` takes.
+    fn name(&self) -> String {
+        match self {
+            AtSpec::Named(s) => s.clone(),
+            AtSpec::Bar { bar } => format!("bar:{}", bar),
+        }
+    }
+}
+
+/// How a transition switches assets: `seamless` cuts, `xfade` crossfades for `xfadeMs`.
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SwitchMode {
+    kind: String,
+    #[serde(default)]
+    xfade_ms: f32,
+    #[serde(default)]
+    align: String,
+}
+
+impl SwitchMode {
+    /// `(fade_ms, fade_align)` for the engine functions.
+    fn parts(mode: &Option<SwitchMode>) -> (f32, &str) {
+        match mode {
+            Some(m) if m.kind == "xfade" => (m.xfade_ms, m.align.as_str()),
+            _ => (0.0, "before"),
+        }
+    }
+}
+
+fn default_transition_at() -> AtSpec { AtSpec::Named("now".to_string()) }
+
+/// Outcome of one command passed to [`apply_commands`], in input order.
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct CommandResult {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+fn when(w: Option<f64>) -> u64 { w.map_or(0, |w| w.max(0.0) as u64) }
+
+fn run_command(cmd: EngineCommand) -> Result<(), String> {
+    let applied = match cmd {
+        EngineCommand::CreateBus { bus_id, options } => {
+            engine_create_bus(&bus_id, options.gain_db.unwrap_or(-6.0));
+            if let Some(lpf) = options.lpf.filter(|l| l.enabled) {
+                engine_set_lpf("bus", &bus_id, lpf.cutoff_hz, lpf.q.unwrap_or(0.707), 0.0, lpf.order.unwrap_or(1));
+            }
+            return Ok(());
+        }
+        EngineCommand::CreateTrack { track_id, bus_id, asset_id, options } => {
+            if !engine_create_track_bus(&track_id, &bus_id, &asset_id, options.pan, options.gain_db) {
+                return Err(format!("unknown asset '{}'", asset_id));
+            }
+            return Ok(());
+        }
+        EngineCommand::SchedulePlay { track_id, when_samples, offset_samples, loop_spec } => {
+            let (mode, start, end, xfade) = LoopSpec::parts(&loop_spec);
+            engine_play_at(&track_id, when(when_samples), offset_samples, mode, start, end, xfade)
+        }
+        EngineCommand::Stop { track_id, when_samples, fade_out_ms } => engine_stop(&track_id, when(when_samples), fade_out_ms),
+        EngineCommand::SetGain { scope, id, when_samples, gain_db, ramp_ms } => match (scope.as_str(), id) {
+            ("master", _) => { engine_set_master_gain(gain_db, ramp_ms); true }
+            ("bus", Some(id)) => engine_set_bus_gain_at(&id, when(when_samples), gain_db, ramp_ms),
+            ("track", Some(id)) => engine_set_track_gain_at(&id, when(when_samples), gain_db, ramp_ms),
+            _ => return Err(format!("setGain: unsupported scope '{}' or missing id", scope)),
+        },
+        EngineCommand::SetLpf { scope, id, cutoff_hz, q, ramp_ms, order } => {
+            engine_set_lpf(&scope, &id, cutoff_hz, q.unwrap_or(0.707), ramp_ms, order.unwrap_or(1))
+        }
+        EngineCommand::SetDucker { target_bus_id, key_bus_id, key_bus_ids, params: p } => {
+            let keys = key_ids(&key_bus_id, &key_bus_ids);
+            if keys.is_empty() { return Err("setDucker: keyBusId or keyBusIds is required".to_string()); }
+            engine_set_ducker_keys(
+                &target_bus_id, &keys,
+                p.threshold_db.unwrap_or(-24.0), p.ratio.unwrap_or(6.0),
+                p.attack_ms.unwrap_or(10.0), p.release_ms.unwrap_or(200.0), p.hold_ms.unwrap_or(0.0),
+                p.max_atten_db.unwrap_or(12.0), p.makeup_db.unwrap_or(0.0),
+                p.detector.as_deref().unwrap_or("peak"), p.rms_window_ms.unwrap_or(20.0), p.key_combine.as_deref().unwrap_or("max"),
+            )
+        }
+        EngineCommand::RemoveDucker { target_bus_id, key_bus_id, key_bus_ids } => {
+            engine_remove_ducker(&target_bus_id, &key_ids(&key_bus_id, &key_bus_ids))
+        }
+        EngineCommand::SetMarkers { track_id, markers_samples, bpm, time_sig } => {
+            // The optional tempo fields put bar 1 at the start of the asset
+            let [bpb, unit] = time_sig.unwrap_or([4, 4]);
+            engine_set_markers(&track_id, markers_samples) && bpm.is_none_or(|bpm| engine_set_tempo(&track_id, bpm, bpb, unit, 0.0))
+        }
+        EngineCommand::SetTempo { track_id, bpm, time_sig, downbeat_samples } => {
+            let [bpb, unit] = time_sig.unwrap_or([4, 4]);
+            engine_set_tempo(&track_id, bpm, bpb, unit, downbeat_samples)
+        }
+        EngineCommand::SetPolyphony { bus_id, max_voices, policy } => engine_set_polyphony(&bus_id, max_voices, &policy),
+        EngineCommand::SetInterpolation { track_id, mode } => match track_id {
+            Some(id) => engine_set_track_interpolation(&id, &mode),
+            None => engine_set_interpolation(&mode),
+        },
+        EngineCommand::SetLoop { track_id, when_samples, loop_spec } => {
+            let (mode, start, end, xfade) = LoopSpec::parts(&loop_spec);
+            engine_set_loop_at(&track_id, when(when_samples), mode, start, end, xfade)
+        }
+        EngineCommand::Transition { track_id, when_samples, at, to_asset_id, loop_spec, mode: switch } => {
+            let (mode, start, end, xfade) = LoopSpec::parts(&loop_spec);
+            let (fade_ms, align) = SwitchMode::parts(&switch);
+            engine_transition_fade_at(&track_id, when(when_samples), &at.name(), &to_asset_id, mode, start, end, xfade, fade_ms, align)
+        }
+        EngineCommand::CreateGroup { group_id, track_ids } => engine_create_group(&group_id, track_ids),
+        EngineCommand::SetGroupMarkers { group_id, markers_samples } => engine_set_group_markers(&group_id, markers_samples),
+        EngineCommand::GroupPlay { group_id, when_samples, offset_samples, loop_spec } => {
+            let (mode, start, end, xfade) = LoopSpec::parts(&loop_spec);
+            engine_group_play_at(&group_id, when(when_samples), offset_samples, mode, start, end, xfade)
+        }
+        EngineCommand::GroupStop { group_id, when_samples, fade_out_ms } => engine_group_stop(&group_id, when(when_samples), fade_out_ms),
+        EngineCommand::GroupTransition { group_id, when_samples, at, to_asset_ids, loop_spec, mode: switch } => {
+            let (mode, start, end, xfade) = LoopSpec::parts(&loop_spec);
+            let (fade_ms, align) = SwitchMode::parts(&switch);
+            engine_group_transition_at(&group_id, when(when_samples), &at.name(), to_asset_ids, mode, start, end, xfade, fade_ms, align)
+        }
+        EngineCommand::SetStemGains { group_id, when_samples, gains_db, ramp_ms } => {
+            engine_set_stem_gains_at(&group_id, when(when_samples), gains_db.into_iter().collect(), ramp_ms)
+        }
+        EngineCommand::UnloadBuffer { buffer_id, defer } => engine_unload_asset(&buffer_id, defer.unwrap_or(true)),
+        EngineCommand::SetMemoryBudget { bytes } => { engine_set_memory_budget(bytes.max(0.0) as u64); true }
+    };
+    if applied { Ok(()) } else { Err("rejected: unknown target or invalid parameters".to_string()) }
+}
+
+/// Applies a JSON array of worklet-schema commands (as produced by the orchestrator in
+/// `game.rs`) and reports one result per command. A single object is treated as a one-element
+/// array; a command that fails to parse or apply does not stop the ones after it.
+pub fn apply_commands(json: &str) -> Result<Vec<CommandResult>, String> {
+    let v: serde_json::Value = serde_json::from_str(json).map_err(|e| e.to_string())?;
+    let items = match v {
+        serde_json::Value::Array(items) => items,
+        other => vec![other],
+    };
+    Ok(items
+        .into_iter()
+        .map(|item| {
+            let res = serde_json::from_value::<EngineCommand>(item)
+                .map_err(|e| e.to_string())
+                .and_then(run_command);
+            match res {
+                Ok(()) => CommandResult { ok: true, error: None },
+                Err(e) => CommandResult { ok: false, error: Some(e) },
+            }
+        })
+        .collect())
 }
 
-// (Bus-level gain/LPF and precise ducking are kept in JS for now; to be ported next.)
+// ----- Tests -----
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+
+    /// Counts allocations made on the current thread so tests can assert the render path is allocation-free.
+    struct CountingAlloc;
+
+    thread_local! {
+        static ALLOCS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    fn count_alloc() { let _ = ALLOCS.try_with(|c| c.set(c.get() + 1)); }
+
+    unsafe impl GlobalAlloc for CountingAlloc {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 { count_alloc(); System.alloc(layout) }
+        unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 { count_alloc(); System.alloc_zeroed(layout) }
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 { count_alloc(); System.realloc(ptr, layout, new_size) }
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) { System.dealloc(ptr, layout) }
+    }
+
+    #[global_allocator]
+    static GLOBAL: CountingAlloc = CountingAlloc;
+
+    fn sine_asset(id: &str, freq: f32, sr: f32, len: usize) {
+        let ch: Vec<f32> = (0..len).map(|n| (2.0 * std::f32::consts::PI * freq * n as f32 / sr).sin()).collect();
+        assert!(engine_register_asset(id, sr, vec![ch]));
+    }
+
+    /// Engine with a transparent master (no headroom, limiter bypassed) so levels are exact.
+    fn init_unity(sr: f32) {
+        engine_init(sr);
+        engine_set_headroom(0.0);
+        engine_set_limiter(false, -1.0, 0, 2.0, 100.0, 3.0);
+    }
+
+    fn max_step(buf: &[f32]) -> f32 {
+        buf.windows(2).map(|w| (w[1] - w[0]).abs()).fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn test_stop_fades_out_and_removes_track() {
+        init_unity(1000.0);
+        assert!(engine_register_asset("dc", 1000.0, vec![vec![1.0; 1000]]));
+        assert!(engine_create_track("t", "dc", 0.0, 0.0));
+        assert!(engine_schedule_play("t", 0, "seamless", 0, -1, 0));
+        assert!(engine_stop("t", 100, 10.0));
+        let mut l = vec![0.0f32; 200];
+        let mut r = vec![0.0f32; 200];
+        engine_process_into(&mut l, &mut r);
+        assert!((l[99] - std::f32::consts::FRAC_1_SQRT_2).abs() < 1e-4);
+        assert!(l[104] > 0.0 && l[104] < l[99]);
+        assert!(l[110..].iter().all(|&v| v == 0.0));
+        AUDIO.with(|a| assert!(!a.borrow().track_index.contains_key("t")));
+    }
+
+    #[test]
+    fn test_track_gain_and_pan_ramp_smoothly() {
+        init_unity(1000.0);
+        assert!(engine_register_asset("dc", 1000.0, vec![vec![1.0; 1000]]));
+        assert!(engine_create_track("t", "dc", 0.0, 0.0));
+        assert!(engine_schedule_play("t", 0, "seamless", 0, -1, 0));
+        assert!(engine_set_track_gain("t", -120.0, 100.0));
+        assert!(engine_set_track_pan("t", 1.0, 100.0));
+        let mut l = vec![0.0f32; 200];
+        let mut r = vec![0.0f32; 200];
+        engine_process_into(&mut l, &mut r);
+        assert!(max_step(&l) < 0.02 && max_step(&r) < 0.02, "ramps should not jump");
+        assert!(l[150].abs() < 1e-5 && r[150].abs() < 1e-5);
+        assert!(!engine_set_track_gain("missing", 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_bus_gain_mute_and_solo() {
+        init_unity(1000.0);
+        assert!(engine_register_asset("dc", 1000.0, vec![vec![1.0; 1000]]));
+        assert!(engine_create_bus("bgm", -6.0));
+        assert!(engine_create_bus("voice", 0.0));
+        assert!(engine_create_track_bus("b", "bgm", "dc", 0.0, 0.0));
+        assert!(engine_create_track_bus("v", "voice", "dc", 0.0, 0.0));
+        assert!(engine_schedule_play("b", 0, "seamless", 0, -1, 0));
+        let mut l = vec![0.0f32; 100];
+        let mut r = vec![0.0f32; 100];
+        engine_process_into(&mut l, &mut r);
+        let p = std::f32::consts::FRAC_1_SQRT_2;
+        assert!((l[50] - p * db_to_lin(-6.0)).abs() < 1e-4);
+
+        assert!(engine_schedule_play("v", 0, "seamless", 0, -1, 0));
+        assert!(engine_set_bus_solo("voice", true));
+        engine_process_into(&mut l, &mut r);
+        assert!((l[99] - p).abs() < 1e-4, "only the soloed bus should remain");
+
+        assert!(engine_set_bus_mute("voice", true));
+        engine_process_into(&mut l, &mut r);
+        assert!(l[99].abs() < 1e-6);
+        assert!(!engine_set_bus_gain("missing", 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_lpf_attenuates_highs_and_passes_lows() {
+        let rms = |v: &[f32]| (v.iter().map(|x| x * x).sum::<f32>() / v.len() as f32).sqrt();
+        init_unity(48000.0);
+        sine_asset("lo", 100.0, 48000.0, 48000);
+        sine_asset("hi", 8000.0, 48000.0, 48000);
+        assert!(engine_create_bus("bgm", 0.0));
+        assert!(engine_create_track_bus("lo", "bgm", "lo", 0.0, 0.0));
+        assert!(engine_create_track_bus("hi", "bgm", "hi", 0.0, 0.0));
+        assert!(engine_set_lpf("bus", "bgm", 500.0, 0.707, 0.0, 2));
+        let mut l = vec![0.0f32; 4800];
+        let mut r = vec![0.0f32; 4800];
+
+        assert!(engine_schedule_play("hi", 0, "seamless", 0, -1, 0));
+        engine_process_into(&mut l, &mut r);
+        assert!(rms(&l[2400..]) < 0.001, "8 kHz should be removed, rms {}", rms(&l[2400..]));
+
+        assert!(engine_stop("hi", 0, 0.0));
+        assert!(engine_schedule_play("lo", 0, "seamless", 0, -1, 0));
+        engine_process_into(&mut l, &mut r);
+        assert!(rms(&l[2400..]) > 0.45, "100 Hz should pass, rms {}", rms(&l[2400..]));
+
+        assert!(engine_set_lpf("track", "lo", 200.0, 0.707, 50.0, 1));
+        assert!(!engine_set_lpf("bus", "missing", 200.0, 0.707, 0.0, 1));
+    }
+
+    #[test]
+    fn test_limiter_ramps_gain_over_lookahead_on_step() {
+        let mut lim = Limiter { enabled: true, lookahead: 96, attack_ms: 2.0, ..Limiter::default() };
+        lim.configure(48000.0);
+        let input = |i: usize| if i < 1000 { 0.1f32 } else { 2.0 };
+        let ceiling = db_to_lin(-1.0);
+        let gmin = ceiling / 2.0;
+        let mut prev = 1.0f32;
+        let mut max_step = 0.0f32;
+        for i in 0..3000 {
+            let (ol, _) = lim.process(input(i), input(i));
+            if i < 96 { continue; }
+            let x = input(i - 96);
+            assert!(ol.abs() <= ceiling + 1e-5, "over the ceiling at {i}: {ol}");
+            let g = ol / x;
+            max_step = max_step.max((g - prev).abs());
+            prev = g;
+        }
+        // Down over the 96-sample attack, not in one step
+        assert!(max_step <= (1.0 - gmin) / 96.0 + 1e-4, "gain step {max_step}");
+        assert!((prev - gmin).abs() < 1e-3, "settles at the required gain, got {prev}");
+    }
+
+    #[test]
+    fn test_limiter_holds_ceiling_without_clipping() {
+        engine_init(48000.0);
+        // The default limiter, with the headroom taken away so the tracks sum well over 0 dBFS
+        engine_set_headroom(0.0);
+        sine_asset("a", 440.0, 48000.0, 48000);
+        for id in ["t1", "t2", "t3"] {
+            assert!(engine_create_track(id, "a", 0.0, 6.0));
+            assert!(engine_schedule_play(id, 0, "seamless", 0, -1, 0));
+        }
+        let mut l = vec![0.0f32; 4800];
+        let mut r = vec![0.0f32; 4800];
+        for _ in 0..4 { engine_process_into(&mut l, &mut r); }
+        let ceiling = db_to_lin(-1.0) + 1e-4;
+        assert!(l.iter().chain(r.iter()).all(|v| v.abs() <= ceiling));
+        let stats = engine_limiter_stats();
+        assert_eq!(stats.clip_count, 0);
+        assert!(stats.max_gain_reduction_db > 6.0, "{:?}", stats);
+        assert!(stats.limited_samples > 0);
+        engine_reset_limiter_stats();
+        assert_eq!(engine_limiter_stats().limited_samples, 0);
+    }
+
+    #[test]
+    fn test_timeline_applies_commands_at_exact_sample() {
+        init_unity(1000.0);
+        assert!(engine_register_asset("dc", 1000.0, vec![vec![1.0; 1000]]));
+        assert!(engine_create_track("t", "dc", 0.0, 0.0));
+        assert!(engine_play_at("t", 37, 0, "seamless", 0, -1, 0));
+        assert!(engine_set_track_gain_at("t", 90, -120.0, 0.0));
+        assert!(engine_stop("t", 300, 0.0));
+        assert!(!engine_transition_at("t", 10, "bar 3", "dc", "seamless", 0, -1, 0));
+        let mut l = vec![0.0f32; 128];
+        let mut r = vec![0.0f32; 128];
+        engine_process_into(&mut l, &mut r);
+        assert!(l[..37].iter().all(|&v| v == 0.0));
+        assert!(l[37..90].iter().all(|&v| v > 0.7));
+        assert!(l[90..].iter().all(|&v| v.abs() < 1e-5));
+        assert_eq!(engine_current_sample(), 128);
+        engine_process_into(&mut l, &mut r);
+        engine_process_into(&mut l, &mut r);
+        AUDIO.with(|a| assert!(!a.borrow().track_index.contains_key("t")));
+    }
+
+    #[test]
+    fn test_events_report_start_loop_marker_transition_and_end() {
+        init_unity(1000.0);
+        assert!(engine_register_asset("a", 1000.0, vec![vec![0.5; 100]]));
+        assert!(engine_register_asset("b", 1000.0, vec![vec![0.5; 50]]));
+        assert!(engine_create_track("t", "a", 0.0, 0.0));
+        assert!(engine_set_markers("t", vec![40]));
+        assert!(engine_play_at("t", 10, 0, "seamless", 0, -1, 0));
+        assert!(engine_transition_at("t", 150, "loopEnd", "b", "none", 0, -1, 0));
+        let mut l = vec![0.0f32; 300];
+        let mut r = vec![0.0f32; 300];
+        engine_process_into(&mut l, &mut r);
+        let t = || Rc::<str>::from("t");
+        assert_eq!(engine_drain_events(), vec![
+            EngineEvent::TrackStarted { track_id: t(), at_samples: 10 },
+            EngineEvent::MarkerReached { track_id: t(), marker: 40, at_samples: 50 },
+            EngineEvent::TrackLooped { track_id: t(), count: 1, at_samples: 110 },
+            EngineEvent::MarkerReached { track_id: t(), marker: 40, at_samples: 150 },
+            EngineEvent::TransitionCompleted { track_id: t(), asset_id: "b".into(), at_samples: 210 },
+            // Markers belong to the track, so they keep firing on the new asset
+            EngineEvent::MarkerReached { track_id: t(), marker: 40, at_samples: 250 },
+            EngineEvent::TrackEnded { track_id: t(), at_samples: 259 },
+        ]);
+        assert!(engine_drain_events().is_empty());
+    }
+
+    #[test]
+    fn test_apply_commands_reports_per_command_results() {
+        init_unity(1000.0);
+        assert!(engine_register_asset("a", 1000.0, vec![vec![0.5; 100]]));
+        let res = apply_commands(r#"[
+            {"type":"createBus","busId":"bgm","options":{"gainDb":0}},
+            {"type":"createTrack","trackId":"t","busId":"bgm","assetId":"a","options":{"gainDb":0,"pan":0}},
+            {"type":"createTrack","trackId":"u","busId":"bgm","assetId":"missing"},
+            {"type":"schedulePlay","trackId":"t","whenSamples":10,"loop":{"mode":"seamless","start":0,"end":null}},
+            {"type":"setGain","scope":"bus","id":"nope","gainDb":-6},
+            {"type":"warp","trackId":"t"},
+            {"type":"setGain","scope":"track","id":"t","gainDb":-6,"rampMs":0,"whenSamples":20}
+        ]"#).unwrap();
+        let oks: Vec<bool> = res.iter().map(|r| r.ok).collect();
+        assert_eq!(oks, vec![true, true, false, true, false, false, true]);
+        assert!(res[2].error.as_deref().unwrap().contains("missing"));
+        assert!(res[5].error.is_some());
+
+        let mut l = vec![0.0f32; 30];
+        let mut r = vec![0.0f32; 30];
+        engine_process_into(&mut l, &mut r);
+        assert_eq!(l[9], 0.0);
+        assert!((l[15] - 0.5 * std::f32::consts::FRAC_1_SQRT_2).abs() < 1e-4);
+        assert!((l[25] - 0.5 * std::f32::consts::FRAC_1_SQRT_2 * db_to_lin(-6.0)).abs() < 1e-4);
+
+        assert!(apply_commands("not json").is_err());
+    }
+
+    #[test]
+    fn test_meters_report_rms_peak_true_peak_and_ducking() {
+        init_unity(48000.0);
+        // fs/4 tone sampled 45 degrees off its crests: samples read 0.707, the waveform peaks at 1.0
+        let tone: Vec<f32> = (0..48000).map(|n| (std::f32::consts::FRAC_PI_2 * n as f32 + std::f32::consts::FRAC_PI_4).sin()).collect();
+        assert!(engine_register_asset("tone", 48000.0, vec![tone.clone(), tone]));
+        assert!(engine_register_asset("dc", 48000.0, vec![vec![0.5; 48000], vec![0.5; 48000]]));
+        assert!(engine_create_bus("bgm", 0.0));
+        assert!(engine_create_bus("voice", 0.0));
+        engine_set_ducker("bgm", "voice", -30.0, 4.0, 5.0, 100.0, 12.0, 0.0);
+        assert!(engine_create_track_bus("m", "bgm", "tone", 0.0, 0.0));
+        assert!(engine_create_track_bus("v", "voice", "dc", 0.0, 0.0));
+        assert!(engine_schedule_play("m", 0, "seamless", 0, -1, 0));
+        let mut l = vec![0.0f32; 19200];
+        let mut r = vec![0.0f32; 19200];
+        engine_process_into(&mut l, &mut r);
+
+        let snap = engine_meter_snapshot();
+        let bgm = snap.buses.iter().find(|b| b.bus_id == "bgm").unwrap();
+        assert!((bgm.level.rms_db + 3.01).abs() < 0.05, "rms {}", bgm.level.rms_db);
+        assert!((bgm.level.peak_db + 3.01).abs() < 0.05, "peak {}", bgm.level.peak_db);
+        assert!(snap.master.true_peak_db > -0.5 && snap.master.true_peak_db < 0.3, "true peak {}", snap.master.true_peak_db);
+        assert!(snap.duckers[0].gain_reduction_db.abs() < 0.01);
+
+        assert!(engine_schedule_play("v", 0, "seamless", 0, -1, 0));
+        engine_process_into(&mut l, &mut r);
+        let snap = engine_meter_snapshot();
+        let d = &snap.duckers[0];
+        assert_eq!((d.target_bus_id.as_str(), d.key_bus_ids.as_slice()), ("bgm", ["voice".to_string()].as_slice()));
+        assert!(d.gain_reduction_db > 3.0, "gr {}", d.gain_reduction_db);
+        let voice = snap.buses.iter().find(|b| b.bus_id == "voice").unwrap();
+        assert!((voice.level.peak_db + 6.02).abs() < 0.05);
+        let json = serde_json::to_string(&snap).unwrap();
+        assert!(json.contains("\"truePeakDb\"") && json.contains("\"gainReductionDb\"") && json.contains("\"busId\":\"bgm\""));
+    }
+
+    #[test]
+    fn test_music_group_stems_stay_sample_locked() {
+        init_unity(1000.0);
+        let ramp = |len: usize, k: f32| -> Vec<f32> { (0..len).map(|n| k * n as f32 / len as f32).collect() };
+        assert!(engine_register_asset("a1", 1000.0, vec![ramp(400, 1.0)]));
+        assert!(engine_register_asset("a2", 1000.0, vec![ramp(400, 1.0)]));
+        assert!(engine_register_asset("b1", 1000.0, vec![ramp(300, -0.5)]));
+        assert!(engine_register_asset("b2", 1000.0, vec![ramp(300, -0.5)]));
+        assert!(engine_register_asset("short", 1000.0, vec![ramp(399, 1.0)]));
+        assert!(engine_create_bus("bgm", 0.0));
+        // Mono stems panned hard apart: a locked pair gives identical left and right channels
+        assert!(engine_create_track_bus("drums", "bgm", "a1", -1.0, 0.0));
+        assert!(engine_create_track_bus("pads", "bgm", "a2", 1.0, 0.0));
+        assert!(engine_create_track_bus("odd", "bgm", "short", 0.0, 0.0));
+        assert!(!engine_create_group("g", vec!["drums".into(), "odd".into()]), "stems of different length cannot lock");
+        assert!(engine_set_markers("drums", vec![250]));
+        assert!(engine_create_group("g", vec!["drums".into(), "pads".into()]));
+        AUDIO.with(|a| assert_eq!(a.borrow().track("pads").unwrap().markers, vec![(250, 250)]));
+
+        let t0 = engine_current_sample();
+        assert!(engine_group_play_at("g", t0 + 37, 10, "seamless", 0, -1, 0));
+        assert!(engine_group_transition_at("g", t0 + 100, "nextMarker", vec!["b1".into(), "b2".into()], "seamless", 0, -1, 0, 0.0, ""));
+        assert!(!engine_group_transition_at("g", t0 + 100, "now", vec!["b1".into()], "seamless", 0, -1, 0, 0.0, ""));
+        assert!(engine_set_stem_gains_at("g", t0 + 600, vec![("drums".into(), -6.0), ("pads".into(), -6.0)], 0.0));
+        assert!(!engine_set_stem_gains_at("g", t0 + 600, vec![("odd".into(), 0.0)], 0.0));
+        assert!(engine_group_stop("g", t0 + 800, 20.0));
+        let mut l = vec![0.0f32; 1000];
+        let mut r = vec![0.0f32; 1000];
+        for c in 0..10 {
+            let (a, b) = (c * 100, c * 100 + 100);
+            engine_process_into(&mut l[a..b], &mut r[a..b]);
+        }
+        assert!(l.iter().zip(&r).all(|(x, y)| (x - y).abs() < 1e-6), "stems drifted apart");
+        let at = |db: f32| -> f32 { db_to_lin(db) * (10 + 200) as f32 / 400.0 };
+        assert!((l[237] - at(0.0)).abs() < 1e-4, "before the marker both play the first section");
+        assert!(l[300] < 0.0, "after the marker both play the second section");
+        let second = |i: usize, db: f32| -> f32 { -0.5 * db_to_lin(db) * ((i - 277) % 300) as f32 / 300.0 };
+        assert!((l[590] - second(590, 0.0)).abs() < 1e-4 && (l[680] - second(680, -6.0)).abs() < 1e-4, "stem gains applied");
+        assert!(l[830..].iter().all(|&v| v == 0.0));
+
+        let events = engine_drain_events();
+        let switched: Vec<u64> = events.iter().filter_map(|e| match e {
+            EngineEvent::TransitionCompleted { at_samples, .. } => Some(*at_samples),
+            _ => None,
+        }).collect();
+        assert_eq!(switched, vec![t0 + 37 + 240; 2]);
+        AUDIO.with(|a| assert!(!a.borrow().track_index.contains_key("drums")));
+    }
+
+    #[test]
+    fn test_tempo_grid_positions_and_quantised_transitions() {
+        init_unity(1000.0);
+        assert!(engine_register_asset("a", 1000.0, vec![vec![0.5; 4000]]));
+        assert!(engine_register_asset("b", 1000.0, vec![vec![0.25; 4000]]));
+        assert!(engine_create_track("m", "a", 0.0, 0.0));
+        assert!(engine_create_track("n", "a", 0.0, 0.0));
+        // 120 bpm at 1 kHz: 500-sample beats, 2000-sample bars of 4/4, bar 1 at sample 100
+        assert!(engine_set_tempo("m", 120.0, 4, 4, 100.0));
+        assert!(engine_set_tempo("n", 120.0, 4, 4, 100.0));
+        assert!(!engine_set_tempo("m", -1.0, 4, 4, 0.0));
+        assert!(engine_musical_position("m").is_none(), "not playing yet");
+        let t0 = engine_current_sample();
+        assert!(engine_play_at("m", t0, 0, "none", 0, -1, 0));
+        assert!(engine_play_at("n", t0, 0, "none", 0, -1, 0));
+        let mut l = vec![0.0f32; 650];
+        let mut r = vec![0.0f32; 650];
+        engine_process_into(&mut l, &mut r);
+
+        let p = engine_musical_position("m").unwrap();
+        assert_eq!((p.bar, p.beat, p.samples_to_next_beat, p.samples_to_next_bar), (1, 2, 450, 1450));
+        assert!((p.beat_fraction - 0.1).abs() < 1e-9);
+        assert!(!engine_transition("n", "bar:0", "b", "none", 0, -1, 0));
+        assert!(!engine_transition("n", "nextBars", "b", "none", 0, -1, 0));
+        assert!(engine_transition("n", "nextBeat", "b", "none", 0, -1, 0));
+        let res = apply_commands(r#"{"type":"transition","trackId":"m","at":{"bar":2},"toAssetId":"b"}"#).unwrap();
+        assert!(res[0].ok);
+        let mut l = vec![0.0f32; 1500];
+        let mut r = vec![0.0f32; 1500];
+        engine_process_into(&mut l, &mut r);
+        let switched: Vec<(String, u64)> = engine_drain_events().iter().filter_map(|e| match e {
+            EngineEvent::TransitionCompleted { track_id, at_samples, .. } => Some((track_id.to_string(), at_samples - t0)),
+            _ => None,
+        }).collect();
+        assert_eq!(switched, vec![("n".to_string(), 1100), ("m".to_string(), 2100)]);
+    }
+
+    #[test]
+    fn test_crossfaded_transition_before_and_centred_on_boundary() {
+        init_unity(1000.0);
+        assert!(engine_register_asset("pos", 1000.0, vec![vec![1.0; 1000], vec![1.0; 1000]]));
+        assert!(engine_register_asset("neg", 1000.0, vec![vec![-1.0; 1000], vec![-1.0; 1000]]));
+        // Balance panned hard apart, so each side hears one track
+        assert!(engine_create_track("x", "pos", -1.0, 0.0));
+        assert!(engine_create_track("y", "pos", 1.0, 0.0));
+        assert!(engine_set_markers("x", vec![500]));
+        assert!(engine_set_markers("y", vec![500]));
+        let t0 = engine_current_sample();
+        assert!(engine_play_at("x", t0, 0, "seamless", 0, -1, 0));
+        assert!(engine_play_at("y", t0, 0, "seamless", 0, -1, 0));
+        // The incoming loops start late enough to pre-roll the whole fade
+        assert!(!engine_transition_fade_at("x", t0, "nextMarker", "neg", "seamless", 200, -1, 0, 100.0, "after"));
+        assert!(engine_transition_fade_at("x", t0, "nextMarker", "neg", "seamless", 200, -1, 0, 100.0, "before"));
+        let json = r#"{"type":"transition","trackId":"y","at":"nextMarker","toAssetId":"neg","loop":{"mode":"seamless","start":200},"mode":{"kind":"xfade","xfadeMs":100,"align":"center"}}"#;
+        assert!(apply_commands(json).unwrap()[0].ok);
+        let mut l = vec![0.0f32; 700];
+        let mut r = vec![0.0f32; 700];
+        engine_process_into(&mut l, &mut r);
+
+        // Equal power: +1 fading out against -1 fading in crosses zero mid-fade
+        assert!((l[399] - 1.0).abs() < 1e-6 && l[450].abs() < 0.02 && (l[500] + 1.0).abs() < 1e-6);
+        assert!((r[449] - 1.0).abs() < 1e-6 && r[500].abs() < 0.02 && (r[550] + 1.0).abs() < 1e-6);
+        assert!(max_step(&l) < 0.05 && max_step(&r) < 0.05, "crossfade should not jump");
+        let done: Vec<(String, u64)> = engine_drain_events().iter().filter_map(|e| match e {
+            EngineEvent::TransitionCompleted { track_id, at_samples, .. } => Some((track_id.to_string(), at_samples - t0)),
+            _ => None,
+        }).collect();
+        assert_eq!(done, vec![("x".to_string(), 500), ("y".to_string(), 550)]);
+    }
+
+    #[test]
+    fn test_crossfaded_transition_pre_rolls_incoming_onto_boundary() {
+        init_unity(1000.0);
+        let ramp: Vec<f32> = (0..1000).map(|i| i as f32 / 1000.0).collect();
+        assert!(engine_register_asset("pos", 1000.0, vec![vec![1.0; 1000], vec![1.0; 1000]]));
+        assert!(engine_register_asset("ramp", 1000.0, vec![ramp.clone(), ramp]));
+        assert!(engine_create_track("x", "pos", -1.0, 0.0));
+        assert!(engine_create_track("y", "pos", 1.0, 0.0));
+        assert!(engine_set_markers("x", vec![500]));
+        assert!(engine_set_markers("y", vec![500]));
+        let t0 = engine_current_sample();
+        assert!(engine_play_at("x", t0, 0, "seamless", 0, -1, 0));
+        assert!(engine_play_at("y", t0, 0, "seamless", 0, -1, 0));
+        // x has 300 samples ahead of its loop start to pre-roll; y only 50 of the 100 it needs
+        assert!(engine_transition_fade_at("x", t0, "nextMarker", "ramp", "seamless", 300, -1, 0, 100.0, "before"));
+        assert!(engine_transition_fade_at("y", t0, "nextMarker", "ramp", "seamless", 50, -1, 0, 100.0, "before"));
+        let mut l = vec![0.0f32; 600];
+        let mut r = vec![0.0f32; 600];
+        engine_process_into(&mut l, &mut r);
+
+        // On the boundary each incoming asset is at its loop start, and it keeps the grid after
+        assert!((l[500] - 0.3).abs() < 1e-6 && (l[550] - 0.35).abs() < 1e-6, "{} {}", l[500], l[550]);
+        assert!((r[500] - 0.05).abs() < 1e-6, "{}", r[500]);
+        // Mid-fade the incoming reads its pre-roll; ahead of sample 0 it is silent
+        let (g_out, g_in) = equal_power(0.5);
+        assert!((l[450] - (g_out + 0.25 * g_in)).abs() < 1e-5, "{}", l[450]);
+        assert!((r[420] - equal_power(0.2).0).abs() < 1e-5, "{}", r[420]);
+        assert!((r[480] - (equal_power(0.8).0 + 0.03 * equal_power(0.8).1)).abs() < 1e-5, "{}", r[480]);
+    }
+
+    #[test]
+    fn test_ducker_hold_rms_multiple_keys_and_removal() {
+        init_unity(1000.0);
+        let mut burst = vec![0.0f32; 2000];
+        burst[..100].fill(1.0);
+        assert!(engine_register_asset("bed", 1000.0, vec![vec![0.5; 2000], vec![0.5; 2000]]));
+        assert!(engine_register_asset("burst", 1000.0, vec![burst.clone(), burst]));
+        assert!(engine_create_bus("bgm", 0.0));
+        assert!(engine_create_bus("voice", 0.0));
+        assert!(engine_create_bus("sfx", 0.0));
+        // Bed on the right, keys on the left
+        assert!(engine_create_track_bus("m", "bgm", "bed", 1.0, 0.0));
+        assert!(engine_create_track_bus("v", "voice", "burst", -1.0, 0.0));
+        assert!(!engine_set_ducker_keys("bgm", &["voice"], -30.0, 4.0, 1.0, 20.0, 0.0, 12.0, 0.0, "loudness", 0.0, "max"));
+        // Voice: RMS detector with a 200 ms hold; sfx: a second, independent ducker on the same target
+        assert!(engine_set_ducker_keys("bgm", &["voice"], -30.0, 4.0, 1.0, 20.0, 200.0, 12.0, 0.0, "rms", 10.0, "max"));
+        engine_set_ducker("bgm", "sfx", -30.0, 4.0, 1.0, 20.0, 12.0, 0.0);
+        assert_eq!(engine_meter_snapshot().duckers.len(), 2);
+        let t0 = engine_current_sample();
+        assert!(engine_play_at("m", t0, 0, "none", 0, -1, 0));
+        assert!(engine_play_at("v", t0, 0, "none", 0, -1, 0));
+        let mut l = vec![0.0f32; 1000];
+        let mut r = vec![0.0f32; 1000];
+        engine_process_into(&mut l, &mut r);
+        let bed = |i: usize| r[i] / 0.5;
+        assert!(bed(99) < db_to_lin(-11.0), "voice ducks the bed, got {}", bed(99));
+        assert!((bed(250) - bed(110)).abs() < 1e-3, "reduction held after the key stops");
+        assert!(bed(600) > 0.99, "released after the hold, got {}", bed(600));
+
+        // Energy sum of two keys each just under threshold (-17 dB each, -14 dB together) crosses it
+        assert!(engine_set_ducker_keys("bgm", &["voice", "sfx"], -16.0, 4.0, 1.0, 20.0, 0.0, 12.0, 0.0, "peak", 0.0, "sum"));
+        assert!(engine_register_asset("low", 1000.0, vec![vec![0.2; 1000], vec![0.2; 1000]]));
+        assert!(engine_create_track_bus("v2", "voice", "low", -1.0, 0.0));
+        assert!(engine_create_track_bus("s2", "sfx", "low", -1.0, 0.0));
+        assert!(engine_remove_ducker("bgm", &["sfx"]));
+        assert!(engine_remove_ducker("bgm", &["voice"]));
+        assert!(!engine_remove_ducker("bgm", &["voice"]), "already removed");
+        assert_eq!(engine_meter_snapshot().duckers[0].key_bus_ids, vec!["voice".to_string(), "sfx".to_string()]);
+        assert!(engine_play_at("v2", 0, 0, "none", 0, -1, 0));
+        assert!(engine_play_at("s2", 0, 0, "none", 0, -1, 0));
+        engine_process_into(&mut l, &mut r);
+        assert!(engine_meter_snapshot().duckers[0].gain_reduction_db > 1.0);
+        assert!(engine_remove_ducker("bgm", &[]));
+        assert!(engine_meter_snapshot().duckers.is_empty());
+    }
+
+    #[test]
+    fn test_render_path_does_not_allocate() {
+        init_unity(1000.0);
+        engine_set_limiter(true, -1.0, 64, 2.0, 100.0, 3.0);
+        sine_asset("a", 50.0, 1000.0, 400);
+        sine_asset("b", 30.0, 1000.0, 300);
+        assert!(engine_register_asset("st", 1000.0, vec![vec![0.3; 500], vec![-0.3; 500]]));
+        assert!(engine_create_bus("bgm", 0.0));
+        assert!(engine_create_bus("voice", 0.0));
+        assert!(engine_set_ducker_keys("bgm", &["voice", "sfx"], -30.0, 6.0, 5.0, 50.0, 30.0, 12.0, 0.0, "rms", 10.0, "sum"));
+        assert!(engine_create_track_bus("music", "bgm", "a", 0.0, 0.0));
+        assert!(engine_create_track_bus("pad", "bgm", "st", 0.5, -3.0));
+        assert!(engine_create_track_bus("vo", "voice", "b", -0.5, 0.0));
+        assert!(engine_set_markers("music", vec![100, 250]));
+        assert!(engine_set_lpf("bus", "bgm", 800.0, 0.707, 50.0, 2));
+        assert!(engine_set_lpf("track", "vo", 300.0, 0.707, 0.0, 1));
+        assert!(engine_set_track_interpolation("music", "sinc"));
+        assert!(engine_set_track_interpolation("pad", "hermite"));
+        assert!(engine_play_at("music", 0, 0, "seamless", 0, -1, 0));
+        assert!(engine_play_at("pad", 0, 0, "xfade", 0, -1, 50));
+        assert!(engine_play_at("vo", 300, 0, "none", 0, -1, 0));
+        assert!(engine_set_polyphony("voice", 1, "queue"));
+        assert!(engine_create_track_bus("vo2", "voice", "b", 0.0, 0.0));
+        assert!(engine_play_at("vo2", 350, 0, "none", 0, -1, 0));
+        assert!(engine_transition_fade_at("music", 900, "nextMarker", "b", "seamless", 0, -1, 0, 40.0, "center"));
+        assert!(engine_set_track_pan_at("pad", 1200, -0.5, 100.0));
+        assert!(engine_set_bus_gain_at("bgm", 1500, -6.0, 200.0));
+        assert!(engine_stop("pad", 2000, 20.0));
+        let mut l = vec![0.0f32; 3000];
+        let mut r = vec![0.0f32; 3000];
+
+        let mut events: Vec<EngineEvent> = Vec::with_capacity(MAX_PENDING_EVENTS);
+        let before = ALLOCS.with(|c| c.get());
+        for chunk in 0..20 {
+            let (a, b) = (chunk * 128, chunk * 128 + 128);
+            engine_process_into(&mut l[a..b], &mut r[a..b]);
+            // The host drains after every block, usually finding nothing
+            if engine_pending_events() == 0 { assert!(engine_drain_events().is_empty()); }
+            engine_drain_events_into(&mut events);
+        }
+        engine_process_into(&mut l, &mut r);
+        engine_drain_events_into(&mut events);
+        assert_eq!(ALLOCS.with(|c| c.get()), before);
+
+        assert!(events.iter().any(|e| matches!(e, EngineEvent::TransitionCompleted { .. })));
+        assert!(events.iter().any(|e| matches!(e, EngineEvent::TrackEnded { track_id, .. } if &**track_id == "vo")));
+        assert!(events.iter().any(|e| matches!(e, EngineEvent::TrackStarted { track_id, at_samples } if &**track_id == "vo2" && *at_samples > 350)));
+        AUDIO.with(|a| assert!(!a.borrow().track_index.contains_key("pad")));
+    }
+
+    #[test]
+    fn test_unload_defers_while_playing_and_budget_evicts_lru() {
+        init_unity(1000.0);
+        let unloaded = |ev: &[EngineEvent]| -> Vec<String> {
+            ev.iter().filter_map(|e| match e { EngineEvent::AssetUnloaded { asset_id, .. } => Some(asset_id.to_string()), _ => None }).collect()
+        };
+        assert!(engine_register_asset("a", 1000.0, vec![vec![0.5; 100], vec![0.5; 100]]));
+        assert!(engine_register_asset("b", 1000.0, vec![vec![0.5; 200]]));
+        assert!(engine_create_track_bus("t", "bgm", "a", 0.0, 0.0));
+        let stats = engine_asset_stats();
+        assert_eq!(stats.total_bytes, 1600);
+        assert_eq!(stats.assets.iter().map(|x| (x.asset_id.as_str(), x.bytes, x.in_use)).collect::<Vec<_>>(), vec![("a", 800, true), ("b", 800, false)]);
+
+        engine_drain_events();
+        assert!(!engine_unload_asset("a", false), "refused while a track holds it");
+        assert!(engine_unload_asset("b", false));
+        assert!(!engine_unload_asset("b", false), "already gone");
+        assert_eq!(unloaded(&engine_drain_events()), vec!["b"]);
+
+        // Replacing keeps the old PCM alive for the playing track until it lets go
+        assert!(engine_play_at("t", 0, 0, "none", 0, -1, 0));
+        assert!(engine_register_asset("a", 1000.0, vec![vec![0.25; 50]]));
+        let stats = engine_asset_stats();
+        assert_eq!(stats.total_bytes, 1000);
+        assert!(stats.assets.iter().any(|x| x.asset_id == "a" && x.unloading && x.bytes == 800));
+        let mut l = vec![0.0f32; 10];
+        let mut r = vec![0.0f32; 10];
+        engine_process_into(&mut l, &mut r);
+        assert!(l[5] > 0.3, "still the old PCM, got {}", l[5]);
+
+        // Deferred unload: hidden from new tracks at once, freed when the track is removed
+        assert!(engine_create_track_bus("u", "bgm", "a", 0.0, 0.0));
+        assert!(engine_unload_asset("a", true));
+        assert!(!engine_create_track_bus("w", "bgm", "a", 0.0, 0.0));
+        engine_process_into(&mut l, &mut r);
+        assert!(unloaded(&engine_drain_events()).is_empty());
+        assert!(engine_stop("u", 0, 0.0));
+        engine_process_into(&mut l, &mut r);
+        assert_eq!(unloaded(&engine_drain_events()), vec!["a"]);
+        // A stopped track is removed once its fade ends
+        assert!(engine_stop("t", 0, 0.0));
+        engine_process_into(&mut l, &mut r);
+        assert!(unloaded(&engine_drain_events()).is_empty(), "a replaced asset is freed silently");
+        assert_eq!(engine_asset_stats().total_bytes, 0);
+
+        // A one-shot that played to its end no longer uses its asset
+        assert!(engine_register_asset("shot", 1000.0, vec![vec![0.5; 50]]));
+        assert!(engine_register_asset("shot2", 1000.0, vec![vec![0.5; 50]]));
+        assert!(engine_create_track_bus("s1", "sfx", "shot", 0.0, 0.0));
+        assert!(engine_create_track_bus("s2", "sfx", "shot2", 0.0, 0.0));
+        assert!(engine_play_at("s1", 0, 0, "none", 0, -1, 0));
+        assert!(engine_play_at("s2", 0, 0, "none", 0, -1, 0));
+        assert!(!engine_unload_asset("shot", false), "still playing");
+        let mut l = vec![0.0f32; 100];
+        let mut r = vec![0.0f32; 100];
+        engine_process_into(&mut l, &mut r);
+        assert!(engine_asset_stats().assets.iter().all(|x| !x.in_use));
+        // ...unless a replay is scheduled
+        assert!(engine_play_at("s2", engine_current_sample() + 50, 0, "none", 0, -1, 0));
+        assert!(!engine_unload_asset("shot2", false));
+        assert!(engine_unload_asset("shot", false));
+        assert!(!engine_play_at("s1", 0, 0, "none", 0, -1, 0), "the ended track went with its asset");
+        engine_set_memory_budget(1);
+        assert_eq!(engine_asset_stats().assets.iter().map(|x| x.asset_id.as_str()).collect::<Vec<_>>(), vec!["shot2"]);
+        engine_process_into(&mut l, &mut r);
+        engine_process_into(&mut l, &mut r);
+        engine_set_memory_budget(1);
+        assert!(engine_asset_stats().assets.is_empty(), "evicted once the replay ended");
+        engine_set_memory_budget(0);
+        engine_drain_events();
+
+        // Budget: the least recently used unused asset goes first, never one in use
+        for id in ["x", "y", "z"] { assert!(engine_register_asset(id, 1000.0, vec![vec![0.0; 100]])); }
+        assert!(engine_create_track_bus("tx", "bgm", "x", 0.0, 0.0));
+        engine_set_memory_budget(800);
+        assert_eq!(unloaded(&engine_drain_events()), vec!["y"]);
+        assert!(engine_register_asset("q", 1000.0, vec![vec![0.0; 100]]));
+        assert_eq!(unloaded(&engine_drain_events()), vec!["z"]);
+        assert!(engine_register_asset("big", 1000.0, vec![vec![0.0; 1000]]));
+        assert_eq!(unloaded(&engine_drain_events()), vec!["q"]);
+        let stats = engine_asset_stats();
+        assert_eq!((stats.total_bytes, stats.budget_bytes), (4400, 800), "in-use and new assets stay over budget");
+        assert_eq!(stats.assets.iter().map(|x| x.asset_id.as_str()).collect::<Vec<_>>(), vec!["big", "x"]);
+    }
+
+    #[test]
+    fn test_sinc_resampler_preserves_tones_and_rejects_aliases() {
+        let sine = |freq: f64, sr: f64, len: usize| -> Vec<f32> {
+            (0..len).map(|n| (2.0 * std::f64::consts::PI * freq * n as f64 / sr).sin() as f32).collect()
+        };
+        let up = SincResampler::new(48000.0 / 44100.0).process(&sine(1000.0, 44100.0, 4410));
+        assert_eq!(up.len(), 4800);
+        let ideal = sine(1000.0, 48000.0, 4800);
+        let err = up[500..4300].iter().zip(&ideal[500..4300]).map(|(a, b)| (a - b).abs()).fold(0.0, f32::max);
+        assert!(err < 2e-3, "max error {}", err);
+
+        // 30 kHz is above the 24 kHz Nyquist of the target rate and must not fold back
+        let down = SincResampler::new(0.5).process(&sine(30000.0, 96000.0, 9600));
+        let rms = (down[500..4300].iter().map(|v| v * v).sum::<f32>() / 3800.0).sqrt();
+        assert!(rms < 0.01, "alias rms {}", rms);
+    }
+
+    #[test]
+    fn test_resampled_asset_keeps_loop_points_and_markers() {
+        init_unity(1000.0);
+        assert!(engine_register_asset_resampled("half", 500.0, vec![vec![0.5; 300]]));
+        assert!(engine_create_track("t", "half", 0.0, 0.0));
+        assert!(engine_set_markers("t", vec![100]));
+        assert!(engine_play_at("t", 0, 0, "seamless", 0, 200, 0));
+        let mut l = vec![0.0f32; 500];
+        let mut r = vec![0.0f32; 500];
+        engine_process_into(&mut l, &mut r);
+        let t = || Rc::<str>::from("t");
+        assert_eq!(engine_drain_events(), vec![
+            EngineEvent::TrackStarted { track_id: t(), at_samples: 0 },
+            EngineEvent::MarkerReached { track_id: t(), marker: 100, at_samples: 200 },
+            EngineEvent::TrackLooped { track_id: t(), count: 1, at_samples: 400 },
+        ]);
+    }
+
+    #[test]
+    fn test_interpolators_rank_by_accuracy_across_loop_wraps() {
+        let mut errs = Vec::new();
+        for mode in ["nearest", "linear", "hermite", "sinc"] {
+            init_unity(1000.0);
+            // 10 whole periods at 500 Hz, played at half speed so every other output sample is fractional
+            sine_asset("s", 25.0, 500.0, 200);
+            assert!(engine_set_interpolation(mode));
+            assert!(engine_create_track("t", "s", 0.0, 0.0));
+            assert!(engine_schedule_play("t", 0, "seamless", 0, -1, 0));
+            let mut l = vec![0.0f32; 1000];
+            let mut r = vec![0.0f32; 1000];
+            engine_process_into(&mut l, &mut r);
+            // Skip the start, where kernels look back past the first sample; wraps at 400 and 800 are included
+            let err = (20..1000)
+                .map(|n| {
+                    let ideal = (2.0 * std::f32::consts::PI * 25.0 * (n as f32 * 0.5) / 500.0).sin() * std::f32::consts::FRAC_1_SQRT_2;
+                    (l[n] - ideal).abs()
+                })
+                .fold(0.0, f32::max);
+            errs.push(err);
+        }
+        assert!(errs[1] < errs[0] && errs[2] < errs[1] && errs[3] < errs[1], "errors {:?}", errs);
+        assert!(errs[2] < 2e-3 && errs[3] < 2e-3, "errors {:?}", errs);
+        assert!(!engine_set_interpolation("cubic-ish"));
+    }
+
+    #[test]
+    fn test_multichannel_layouts_downmix_and_stereo_balance() {
+        init_unity(1000.0);
+        // 5.1 with a distinct DC level per channel: L R C LFE Ls Rs
+        let levels = [0.1f32, 0.2, 0.3, 0.9, 0.4, 0.5];
+        let six: Vec<Vec<f32>> = levels.iter().map(|&v| vec![v; 100]).collect();
+        assert!(!engine_register_asset_layout("bad", 1000.0, six.clone(), "7.1", false));
+        assert!(engine_register_asset_layout("surround", 1000.0, six, "5.1", false));
+        assert!(engine_create_track("t", "surround", 0.0, 0.0));
+        assert!(engine_schedule_play("t", 0, "seamless", 0, -1, 0));
+        let mut l = vec![0.0f32; 10];
+        let mut r = vec![0.0f32; 10];
+        engine_process_into(&mut l, &mut r);
+        let h = std::f32::consts::FRAC_1_SQRT_2;
+        // Stereo result plays at unity in the centre; the LFE does not leak in
+        assert!((l[5] - (0.1 + h * 0.3 + h * 0.4)).abs() < 1e-5, "{}", l[5]);
+        assert!((r[5] - (0.2 + h * 0.3 + h * 0.5)).abs() < 1e-5, "{}", r[5]);
+
+        // Balance to the right leaves the right side untouched and fades the left
+        assert!(engine_set_track_pan("t", 0.5, 0.0));
+        engine_process_into(&mut l, &mut r);
+        assert!((r[5] - (0.2 + h * 0.3 + h * 0.5)).abs() < 1e-5);
+        assert!((l[5] - (0.1 + h * 0.3 + h * 0.4) * h).abs() < 1e-5);
+
+        // 10 channels have no usual layout: auto falls back to an equal-weight fold-down
+        let ten: Vec<Vec<f32>> = (0..10).map(|c| vec![0.1 * c as f32; 100]).collect();
+        assert!(engine_register_asset("ten", 1000.0, ten));
+        assert!(engine_create_track("u", "ten", 0.0, 0.0));
+        assert!(engine_stop("t", 0, 0.0));
+        assert!(engine_schedule_play("u", 0, "seamless", 0, -1, 0));
+        engine_process_into(&mut l, &mut r);
+        assert!((l[5] - 0.45).abs() < 1e-5 && (r[5] - 0.45).abs() < 1e-5, "{} {}", l[5], r[5]);
+    }
+
+    #[test]
+    fn test_polyphony_policies_steal_refuse_and_queue() {
+        // Events are returned with times relative to the start of each run
+        let run = |policy: &str| {
+            init_unity(1000.0);
+            let t0 = engine_current_sample();
+            assert!(engine_register_asset("short", 1000.0, vec![vec![0.25; 50]]));
+            assert!(engine_create_bus("sfx", 0.0));
+            assert!(engine_set_polyphony("sfx", 1, policy));
+            assert!(engine_create_track_bus("a", "sfx", "short", 0.0, 0.0));
+            assert!(engine_create_track_bus("b", "sfx", "short", 0.0, 0.0));
+            assert!(engine_play_at("a", t0, 0, "none", 0, -1, 0));
+            assert!(engine_play_at("b", t0 + 10, 0, "none", 0, -1, 0));
+            let mut l = vec![0.0f32; 200];
+            let mut r = vec![0.0f32; 200];
+            engine_process_into(&mut l, &mut r);
+            let mut ev = engine_drain_events();
+            for e in ev.iter_mut() {
+                match e {
+                    EngineEvent::TrackStarted { at_samples, .. }
+                    | EngineEvent::TrackEnded { at_samples, .. }
+                    | EngineEvent::PolyphonyLimited { at_samples, .. } => *at_samples -= t0,
+                    _ => {}
+                }
+            }
+            (ev, l)
+        };
+        let id = |s: &str| Rc::<str>::from(s);
+        let gone = |t: &str| AUDIO.with(|a| !a.borrow().track_index.contains_key(t));
+        let limited = |dropped: Option<&str>| EngineEvent::PolyphonyLimited { bus_id: id("sfx"), dropped_track_id: dropped.map(id), at_samples: 10 };
+        let g = 0.25 * std::f32::consts::FRAC_1_SQRT_2;
+
+        let (ev, l) = run("dropOldest");
+        assert_eq!(ev[1], limited(Some("a")));
+        assert!(ev.contains(&EngineEvent::TrackStarted { track_id: id("b"), at_samples: 10 }));
+        assert!(ev.contains(&EngineEvent::TrackEnded { track_id: id("a"), at_samples: 19 }));
+        assert!(l[15] > g && l[15] < 2.0 * g); // a fading out over b
+        assert!(gone("a"));
+
+        let (ev, _) = run("dropNewest");
+        assert_eq!(ev[1], limited(Some("b")));
+        assert!(!ev.iter().any(|e| matches!(e, EngineEvent::TrackStarted { track_id, .. } if &**track_id == "b")));
+        assert!(gone("b"));
+
+        let (ev, l) = run("queue");
+        assert_eq!(ev[1], limited(None));
+        assert!(ev.contains(&EngineEvent::TrackEnded { track_id: id("a"), at_samples: 49 }));
+        assert!(ev.contains(&EngineEvent::TrackStarted { track_id: id("b"), at_samples: 49 }));
+        assert!(l[..98].iter().all(|&v| (v - g).abs() < 1e-6), "queued voice must start without a gap");
+        assert!(!engine_set_polyphony("sfx", 1, "roundRobin"));
+        assert!(AUDIO.with(|a| a.borrow().buses.iter().all(|b| b.queue.capacity() >= MAX_QUEUED_VOICES)));
+    }
+
+    #[test]
+    fn test_raising_polyphony_starts_queued_voices() {
+        init_unity(1000.0);
+        let t0 = engine_current_sample();
+        assert!(engine_register_asset("long", 1000.0, vec![vec![0.25; 1000]]));
+        assert!(engine_create_bus("sfx", 0.0));
+        assert!(engine_set_polyphony("sfx", 1, "queue"));
+        for id in ["a", "b", "c", "d"] {
+            assert!(engine_create_track_bus(id, "sfx", "long", 0.0, 0.0));
+            assert!(engine_play_at(id, t0, 0, "none", 0, -1, 0));
+        }
+        let mut l = vec![0.0f32; 10];
+        let mut r = vec![0.0f32; 10];
+        engine_process_into(&mut l, &mut r);
+        let playing = || AUDIO.with(|a| a.borrow().tracks.iter().flatten().filter(|t| t.playing).map(|t| t.id.to_string()).collect::<Vec<_>>());
+        assert_eq!(playing(), vec!["a"]);
+
+        // Room for two more: the oldest queued requests take it, the last keeps waiting
+        engine_drain_events();
+        assert!(engine_set_polyphony("sfx", 3, "queue"));
+        assert_eq!(playing(), vec!["a", "b", "c"]);
+        let now = engine_current_sample();
+        assert_eq!(engine_drain_events(), vec![
+            EngineEvent::TrackStarted { track_id: Rc::from("b"), at_samples: now },
+            EngineEvent::TrackStarted { track_id: Rc::from("c"), at_samples: now },
+        ]);
+        assert_eq!(AUDIO.with(|a| a.borrow().buses[a.borrow().bus_index["sfx"]].queue.len()), 1);
+
+        // Leaving `queue` refuses what is still waiting, as `dropNewest` does
+        assert!(engine_create_track_bus("e", "sfx", "long", 0.0, 0.0));
+        assert!(engine_play_at("e", engine_current_sample(), 0, "none", 0, -1, 0));
+        engine_process_into(&mut l, &mut r);
+        engine_drain_events();
+        assert!(engine_set_polyphony("sfx", 3, "dropOldest"));
+        let now = engine_current_sample();
+        assert_eq!(engine_drain_events(), ["d", "e"].map(|t| EngineEvent::PolyphonyLimited { bus_id: Rc::from("sfx"), dropped_track_id: Some(Rc::from(t)), at_samples: now }));
+        assert!(AUDIO.with(|a| !a.borrow().track_index.contains_key("d") && !a.borrow().track_index.contains_key("e")));
+        assert_eq!(playing(), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_xfade_loop_rejects_short_loop() {
+        init_unity(1000.0);
+        sine_asset("a", 7.3, 1000.0, 1000);
+        assert!(engine_create_track("t", "a", 0.0, 0.0));
+        // 300 ms crossfade needs at least 600 samples of loop
+        assert!(!engine_schedule_play("t", 0, "xfade", 0, 500, 300));
+        assert!(!engine_set_loop("t", "xfade", 100, 600, 300));
+        assert!(engine_schedule_play("t", 0, "xfade", 0, 600, 300));
+    }
+
+    #[test]
+    fn test_xfade_loop_scheduled_before_switch_fits_new_asset() {
+        init_unity(1000.0);
+        let t0 = engine_current_sample();
+        sine_asset("long", 7.3, 1000.0, 1000);
+        sine_asset("short", 7.3, 1000.0, 200);
+        assert!(engine_create_track("t", "long", 0.0, 0.0));
+        // The loop (0..1000, 300 ms crossfade) is checked against "long", then the track moves to "short"
+        assert!(engine_play_at("t", t0 + 50, 0, "xfade", 0, 1000, 300));
+        assert!(engine_transition("t", "now", "short", "none", 0, -1, 0));
+        let mut l = vec![0.0f32; 1000];
+        let mut r = vec![0.0f32; 1000];
+        engine_process_into(&mut l, &mut r);
+        let cfg = AUDIO.with(|a| a.borrow().track("t").map(|t| t.loop_cfg)).expect("track");
+        assert_eq!((cfg.end, cfg.xfade), (Some(200), 100));
+        assert!(engine_drain_events().iter().any(|e| matches!(e, EngineEvent::TrackLooped { .. })));
+        assert!(l.iter().all(|v| v.is_finite() && v.abs() <= 1.0));
+    }
+
+    #[test]
+    fn test_xfade_loop_is_continuous_across_wrap() {
+        init_unity(1000.0);
+        sine_asset("a", 7.3, 1000.0, 1000);
+        assert!(engine_create_track("t", "a", 0.0, 0.0));
+        let mut l = vec![0.0f32; 3000];
+        let mut r = vec![0.0f32; 3000];
+
+        assert!(engine_schedule_play("t", 0, "seamless", 0, -1, 0));
+        engine_process_into(&mut l, &mut r);
+        assert!(max_step(&l) > 0.5, "seamless wrap of an unaligned loop should jump");
+
+        assert!(engine_schedule_play("t", 0, "xfade", 0, -1, 100));
+        engine_process_into(&mut l, &mut r);
+        assert!(max_step(&l) < 0.1, "xfade wrap should be smooth, max step {}", max_step(&l));
+    }
+}
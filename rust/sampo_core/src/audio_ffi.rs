@@ -15,3 +15,32 @@ pub extern "C" fn ffi_audio_process_into(out_l_ptr: *mut f32, out_r_ptr: *mut f3
     }
 }
 
+
+/// Writes the pending engine events as a JSON array into `buf` and returns its byte length.
+/// If `cap` is too small nothing is written or drained; call again with at least the returned size.
+#[no_mangle]
+pub extern "C" fn ffi_audio_drain_events(buf: *mut u8, cap: usize) -> usize {
+    let json = if engine_pending_events() == 0 {
+        std::borrow::Cow::Borrowed("[]")
+    } else {
+        std::borrow::Cow::Owned(serde_json::to_string(&engine_peek_events()).unwrap_or_else(|_| "[]".to_string()))
+    };
+    let bytes = json.as_bytes();
+    if buf.is_null() || bytes.len() > cap { return bytes.len() }
+    unsafe { std::ptr::copy_nonoverlapping(bytes.as_ptr(), buf, bytes.len()) }
+    engine_drain_events();
+    bytes.len()
+}
+
+/// Applies a UTF-8 JSON command array (see `apply_commands`). Returns the number of commands
+/// that failed, or -1 if the input is not valid UTF-8 JSON.
+#[no_mangle]
+pub extern "C" fn ffi_audio_apply_commands(json_ptr: *const u8, len: usize) -> i32 {
+    if json_ptr.is_null() { return -1 }
+    let bytes = unsafe { slice::from_raw_parts(json_ptr, len) };
+    let json = match std::str::from_utf8(bytes) { Ok(s) => s, Err(_) => return -1 };
+    match apply_commands(json) {
+        Ok(res) => res.iter().filter(|r| !r.ok).count() as i32,
+        Err(_) => -1,
+    }
+}
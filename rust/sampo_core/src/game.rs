@@ -42,7 +42,7 @@ fn loop_json(mode: &str, start: Option<u32>, end: Option<i32>, crossfade_ms: Opt
 }
 
 fn crossfade_to(cmds: &mut Vec<serde_json::Value>, track_id: &str, asset_id: &str, bus: &str, loop_v: serde_json::Value, fade_ms: u32) {
-    // クロスフェード: 新トラックを-60dBで開始 → rampで0dBへ、旧トラック群を-60dBへ
+    // クロスフェード: 新トラックを-60dBで開始 → rampで0dBへ、旧トラック群はフェードアウト停止
     cmds.push(serde_json::json!({
         "type": "createTrack",
         "trackId": track_id,
@@ -53,10 +53,10 @@ fn crossfade_to(cmds: &mut Vec<serde_json::Value>, track_id: &str, asset_id: &st
     cmds.push(serde_json::json!({ "type": "schedulePlay", "trackId": track_id, "loop": loop_v }));
     // 新トラックをフェードイン
     cmds.push(serde_json::json!({ "type": "setGain", "scope": "track", "id": track_id, "gainDb": 0, "rampMs": fade_ms }));
-    // 既知の他BGMトラックはフェードアウト（停止の明示は行わず、無音で回し続ける）
+    // 既知の他BGMトラックはフェードアウトして停止（フェード終了後にエンジン側で破棄される）
     for other in ["bgm-root1", "bgm-root2", "bgm-root3"].iter() {
         if *other != track_id {
-            cmds.push(serde_json::json!({ "type": "setGain", "scope": "track", "id": other, "gainDb": -60, "rampMs": fade_ms }));
+            cmds.push(serde_json::json!({ "type": "stop", "trackId": other, "fadeOutMs": fade_ms }));
         }
     }
 }
@@ -1,35 +1,143 @@
-use geo::algorithm::{contains::Contains, euclidean_distance::EuclideanDistance};
-use geo::{LineString, Point, Polygon};
+use geo::algorithm::{
+    bounding_rect::BoundingRect, closest_point::ClosestPoint, contains::Contains, euclidean_distance::EuclideanDistance,
+    geodesic_distance::GeodesicDistance, map_coords::MapCoords,
+};
+use geo::{Closest, Line, LineString, Point, Polygon};
 use geojson::{Feature, GeoJson, Value};
-use serde::Deserialize;
+use rstar::primitives::{GeomWithData, Rectangle};
+use rstar::RTree;
 use std::collections::HashMap;
 use wasm_bindgen::prelude::*;
 mod game;
 pub use game::*;
-#[cfg(target_arch = "wasm32")]
+mod road_graph;
+use road_graph::RoadGraph;
 use js_sys::{Array as JsArray, Float32Array};
 
 #[derive(Debug, Clone)]
 struct Road {
     id: String,
-    line: LineString<f64>,
+    line_m: LineString<f64>, // in the state's local frame, projected once at ingest
+    along_m: Vec<f64>,       // geodesic meters from the start to each vertex
+}
+
+impl Road {
+    fn new(id: String, line: LineString<f64>, frame: &LocalFrame) -> Self {
+        let mut along_m = vec![0.0];
+        for l in line.lines() {
+            let d = Point::from(l.start).geodesic_distance(&Point::from(l.end));
+            along_m.push(along_m[along_m.len() - 1] + d);
+        }
+        along_m.truncate(line.0.len());
+        Road { id, line_m: line.map_coords(|c| frame.forward(c.into()).into()), along_m }
+    }
 }
 
 #[derive(Debug, Clone)]
 struct Area {
     id: String,
-    poly: Polygon<f64>,
+    poly_m: Polygon<f64>, // local frame; containment is tested against the projected query point
+}
+
+impl Area {
+    fn new(id: String, poly: Polygon<f64>, frame: &LocalFrame) -> Self {
+        Area { id, poly_m: poly.map_coords(|c| frame.forward(c.into()).into()) }
+    }
+}
+
+/// Spherical azimuthal equidistant projection centred on the dataset, in meters east/north of
+/// the origin. Distances and bearings from the origin are exact and other ones are off by well
+/// under 0.1% across a city, with no blow-up towards the poles as with Web Mercator.
+#[derive(Debug, Clone, Copy, Default)]
+struct LocalFrame {
+    lng0: f64, // degrees
+    sin_lat0: f64,
+    cos_lat0: f64,
 }
 
+const EARTH_RADIUS_M: f64 = 6_371_008.8;
+
+impl LocalFrame {
+    fn new(lng0: f64, lat0: f64) -> Self {
+        let (sin_lat0, cos_lat0) = lat0.to_radians().sin_cos();
+        LocalFrame { lng0, sin_lat0, cos_lat0 }
+    }
+
+    /// Origin at the mean of all vertices of the features the state will keep.
+    fn centroid_of(features: &[Feature]) -> Self {
+        let (mut sx, mut sy, mut n) = (0.0, 0.0, 0usize);
+        let mut add = |c: &Vec<f64>| if c.len() >= 2 { sx += c[0]; sy += c[1]; n += 1; };
+        for geom in features.iter().filter_map(|f| f.geometry.as_ref()) {
+            match &geom.value {
+                Value::LineString(cs) => cs.iter().for_each(&mut add),
+                Value::Polygon(rings) | Value::MultiLineString(rings) => rings.iter().flatten().for_each(&mut add),
+                Value::MultiPolygon(polys) => polys.iter().flatten().flatten().for_each(&mut add),
+                _ => {}
+            }
+        }
+        if n == 0 { return LocalFrame::new(0.0, 0.0); }
+        LocalFrame::new(sx / n as f64, sy / n as f64)
+    }
+
+    /// Lng/lat degrees to local meters.
+    fn forward(&self, p: Point<f64>) -> Point<f64> {
+        let (sin_lat, cos_lat) = p.y().to_radians().sin_cos();
+        let (sin_dl, cos_dl) = (p.x() - self.lng0).to_radians().sin_cos();
+        let cos_c = (self.sin_lat0 * sin_lat + self.cos_lat0 * cos_lat * cos_dl).clamp(-1.0, 1.0);
+        let c = cos_c.acos();
+        let k = if c < 1e-12 { 1.0 } else { c / c.sin() };
+        Point::new(
+            EARTH_RADIUS_M * k * cos_lat * sin_dl,
+            EARTH_RADIUS_M * k * (self.cos_lat0 * sin_lat - self.sin_lat0 * cos_lat * cos_dl),
+        )
+    }
+
+    /// Local meters back to lng/lat degrees.
+    fn inverse(&self, p: Point<f64>) -> Point<f64> {
+        let rho = p.x().hypot(p.y());
+        let lat0 = self.sin_lat0.atan2(self.cos_lat0);
+        if rho < 1e-9 { return Point::new(self.lng0, lat0.to_degrees()); }
+        let (sin_c, cos_c) = (rho / EARTH_RADIUS_M).sin_cos();
+        let lat = (cos_c * self.sin_lat0 + p.y() * sin_c * self.cos_lat0 / rho).clamp(-1.0, 1.0).asin();
+        let dl = (p.x() * sin_c).atan2(rho * self.cos_lat0 * cos_c - p.y() * self.sin_lat0 * sin_c);
+        Point::new(self.lng0 + dl.to_degrees(), lat.to_degrees())
+    }
+}
+
+/// Local-frame segment of `roads[data]`.
+type RoadSegment = GeomWithData<Line<f64>, usize>;
+/// Local-frame bounding box of `areas[data]`.
+type AreaEnvelope = GeomWithData<Rectangle<[f64; 2]>, usize>;
+
 #[derive(Default)]
 struct State {
+    frame: LocalFrame,
     roads: Vec<Road>,
     areas: Vec<Area>,
+    road_index: RTree<RoadSegment>,
+    area_index: RTree<AreaEnvelope>,
+    graph: RoadGraph,
+}
+
+impl State {
+    /// Bulk-loads the bounding-box trees queries prune with and joins the roads into the route
+    /// graph; call once all features are in.
+    fn build_index(&mut self) {
+        self.graph = RoadGraph::build(&self.roads, &self.frame, SNAP_TOLERANCE_M.with(|t| t.get()));
+        let segments = self.roads.iter().enumerate().flat_map(|(i, road)| road_segments(road).map(move |l| GeomWithData::new(l, i)));
+        self.road_index = RTree::bulk_load(segments.collect());
+        let envelopes = self.areas.iter().enumerate().filter_map(|(i, area)| {
+            let r = area.poly_m.bounding_rect()?;
+            Some(GeomWithData::new(Rectangle::from_corners(r.min().x_y().into(), r.max().x_y().into()), i))
+        });
+        self.area_index = RTree::bulk_load(envelopes.collect());
+    }
 }
 
 thread_local! { static STATE: std::cell::RefCell<State> = std::cell::RefCell::new(State::default()); }
+// Route graph snapping tolerance, kept across `init_geojson` calls
+thread_local! { static SNAP_TOLERANCE_M: std::cell::Cell<f64> = const { std::cell::Cell::new(road_graph::DEFAULT_SNAP_TOLERANCE_M) }; }
 
-// Audio engine core module (pure Rust). Wasm wrappers will be introduced separately.
 mod audio;
 #[cfg(feature = "ffi")]
 mod audio_ffi;
@@ -39,8 +147,7 @@ mod audio_ffi;
 #[wasm_bindgen]
 pub fn audio_init(sample_rate: f32) { crate::audio::engine_init(sample_rate) }
 
-#[wasm_bindgen]
-pub fn audio_register_asset(id: &str, sample_rate: f32, channels: JsArray) -> bool {
+fn channels_from_js(channels: JsArray) -> Vec<Vec<f32>> {
     let mut ch_vec: Vec<Vec<f32>> = Vec::new();
     for v in channels.values() {
         let v = v.unwrap_or(JsValue::UNDEFINED);
@@ -49,7 +156,45 @@ pub fn audio_register_asset(id: &str, sample_rate: f32, channels: JsArray) -> bo
         arr.copy_to(&mut dst[..]);
         ch_vec.push(dst);
     }
-    crate::audio::engine_register_asset(id, sample_rate, ch_vec)
+    ch_vec
+}
+
+#[wasm_bindgen]
+pub fn audio_register_asset(id: &str, sample_rate: f32, channels: JsArray) -> bool {
+    crate::audio::engine_register_asset(id, sample_rate, channels_from_js(channels))
+}
+
+/// Registers an asset converted to the engine rate with a windowed-sinc resampler
+/// (call after `audio_init`). Loop points and markers stay in the asset's original samples.
+#[wasm_bindgen]
+pub fn audio_register_asset_resampled(id: &str, sample_rate: f32, channels: JsArray) -> bool {
+    crate::audio::engine_register_asset_resampled(id, sample_rate, channels_from_js(channels))
+}
+
+/// Registers an asset with an explicit channel layout (`mono`, `stereo`, `3.0`, `quad`, `lcrs`,
+/// `5.0`, `5.1`, `6.1`, `7.1`, `ambix` or `auto`); multichannel assets are downmixed to stereo.
+#[wasm_bindgen]
+pub fn audio_register_asset_layout(id: &str, sample_rate: f32, channels: JsArray, layout: &str, resample: bool) -> bool {
+    crate::audio::engine_register_asset_layout(id, sample_rate, channels_from_js(channels), layout, resample)
+}
+
+/// Unloads an asset; with `defer` an asset still playing is freed once its tracks let go,
+/// otherwise it is refused (returns false).
+#[wasm_bindgen]
+pub fn audio_unload_asset(id: &str, defer: bool) -> bool {
+    crate::audio::engine_unload_asset(id, defer)
+}
+
+/// Caps registered PCM at `bytes` (0 = unlimited), evicting unused assets least recently used first.
+#[wasm_bindgen]
+pub fn audio_set_memory_budget(bytes: f64) {
+    crate::audio::engine_set_memory_budget(bytes.max(0.0) as u64)
+}
+
+/// Per-asset and total PCM bytes as JSON.
+#[wasm_bindgen]
+pub fn audio_asset_stats() -> String {
+    serde_json::to_string(&crate::audio::engine_asset_stats()).unwrap_or_else(|_| "{}".to_string())
 }
 
 #[wasm_bindgen]
@@ -67,42 +212,258 @@ pub fn audio_schedule_play(track_id: &str, offset_samples: u32, loop_mode: &str,
     crate::audio::engine_schedule_play(track_id, offset_samples, loop_mode, loop_start, loop_end, xfade_ms)
 }
 
+/// Applies a JSON array of worklet-schema commands to the Rust engine. Returns a JSON array
+/// with one `{"ok":bool,"error"?:string}` per command, or `{"error":...}` if the input is not JSON.
+#[wasm_bindgen]
+pub fn audio_apply_commands(json: &str) -> String {
+    match crate::audio::apply_commands(json) {
+        Ok(res) => serde_json::to_string(&res).unwrap_or_else(|_| "[]".to_string()),
+        Err(e) => serde_json::json!({ "error": e }).to_string(),
+    }
+}
+
+/// JSON array of engine events since the last call, e.g.
+/// `[{"type":"trackEnded","trackId":"voice-1","atSamples":48000}]`.
+#[wasm_bindgen]
+pub fn audio_drain_events() -> String {
+    if crate::audio::engine_pending_events() == 0 { return "[]".to_string(); }
+    serde_json::to_string(&crate::audio::engine_drain_events()).unwrap_or_else(|_| "[]".to_string())
+}
+
+/// Engine sample clock; hosts add their look-ahead to this when computing `when_samples`.
+#[wasm_bindgen]
+pub fn audio_current_sample() -> f64 { crate::audio::engine_current_sample() as f64 }
+
+#[wasm_bindgen]
+pub fn audio_play_at(track_id: &str, when_samples: f64, offset_samples: u32, loop_mode: &str, loop_start: u32, loop_end: i32, xfade_ms: u32) -> bool {
+    crate::audio::engine_play_at(track_id, when_samples.max(0.0) as u64, offset_samples, loop_mode, loop_start, loop_end, xfade_ms)
+}
+
+#[wasm_bindgen]
+pub fn audio_set_loop_at(track_id: &str, when_samples: f64, loop_mode: &str, loop_start: u32, loop_end: i32, xfade_ms: u32) -> bool {
+    crate::audio::engine_set_loop_at(track_id, when_samples.max(0.0) as u64, loop_mode, loop_start, loop_end, xfade_ms)
+}
+
 #[wasm_bindgen]
 pub fn audio_set_loop(track_id: &str, loop_mode: &str, loop_start: u32, loop_end: i32, xfade_ms: u32) -> bool {
     crate::audio::engine_set_loop(track_id, loop_mode, loop_start, loop_end, xfade_ms)
 }
 
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn audio_transition_at(track_id: &str, when_samples: f64, at: &str, to_asset_id: &str, loop_mode: &str, loop_start: u32, loop_end: i32, xfade_ms: u32) -> bool {
+    crate::audio::engine_transition_at(track_id, when_samples.max(0.0) as u64, at, to_asset_id, loop_mode, loop_start, loop_end, xfade_ms)
+}
+
+/// Transition that crossfades over `fade_ms`; `fade_align` is `before` (ends on the boundary)
+/// or `center` (centred on it).
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn audio_transition_fade_at(track_id: &str, when_samples: f64, at: &str, to_asset_id: &str, loop_mode: &str, loop_start: u32, loop_end: i32, xfade_ms: u32, fade_ms: f32, fade_align: &str) -> bool {
+    crate::audio::engine_transition_fade_at(track_id, when_samples.max(0.0) as u64, at, to_asset_id, loop_mode, loop_start, loop_end, xfade_ms, fade_ms, fade_align)
+}
+
+#[wasm_bindgen]
+pub fn audio_create_bus(bus_id: &str, gain_db: f32) -> bool {
+    crate::audio::engine_create_bus(bus_id, gain_db)
+}
+
+#[wasm_bindgen]
+pub fn audio_set_bus_gain(bus_id: &str, gain_db: f32, ramp_ms: f32) -> bool {
+    crate::audio::engine_set_bus_gain(bus_id, gain_db, ramp_ms)
+}
+
+#[wasm_bindgen]
+pub fn audio_set_bus_gain_at(bus_id: &str, when_samples: f64, gain_db: f32, ramp_ms: f32) -> bool {
+    crate::audio::engine_set_bus_gain_at(bus_id, when_samples.max(0.0) as u64, gain_db, ramp_ms)
+}
+
+#[wasm_bindgen]
+pub fn audio_set_bus_mute(bus_id: &str, mute: bool) -> bool {
+    crate::audio::engine_set_bus_mute(bus_id, mute)
+}
+
+#[wasm_bindgen]
+pub fn audio_set_bus_solo(bus_id: &str, solo: bool) -> bool {
+    crate::audio::engine_set_bus_solo(bus_id, solo)
+}
+
+#[wasm_bindgen]
+pub fn audio_set_lpf(scope: &str, id: &str, cutoff_hz: f32, q: f32, ramp_ms: f32, order: u8) -> bool {
+    crate::audio::engine_set_lpf(scope, id, cutoff_hz, q, ramp_ms, order)
+}
+
+#[wasm_bindgen]
+pub fn audio_set_track_gain(track_id: &str, gain_db: f32, ramp_ms: f32) -> bool {
+    crate::audio::engine_set_track_gain(track_id, gain_db, ramp_ms)
+}
+
+#[wasm_bindgen]
+pub fn audio_set_track_gain_at(track_id: &str, when_samples: f64, gain_db: f32, ramp_ms: f32) -> bool {
+    crate::audio::engine_set_track_gain_at(track_id, when_samples.max(0.0) as u64, gain_db, ramp_ms)
+}
+
+#[wasm_bindgen]
+pub fn audio_set_track_pan_at(track_id: &str, when_samples: f64, pan: f32, ramp_ms: f32) -> bool {
+    crate::audio::engine_set_track_pan_at(track_id, when_samples.max(0.0) as u64, pan, ramp_ms)
+}
+
+#[wasm_bindgen]
+pub fn audio_set_track_pan(track_id: &str, pan: f32, ramp_ms: f32) -> bool {
+    crate::audio::engine_set_track_pan(track_id, pan, ramp_ms)
+}
+
+/// Voice limit for a bus; `policy` is `dropOldest`, `dropNewest` or `queue`, `max_voices = 0` lifts it.
+#[wasm_bindgen]
+pub fn audio_set_polyphony(bus_id: &str, max_voices: u32, policy: &str) -> bool {
+    crate::audio::engine_set_polyphony(bus_id, max_voices, policy)
+}
+
+/// Engine-wide interpolator: `nearest`, `linear`, `hermite` or `sinc`.
+#[wasm_bindgen]
+pub fn audio_set_interpolation(mode: &str) -> bool {
+    crate::audio::engine_set_interpolation(mode)
+}
+
+/// Per-track interpolator; `default` follows the engine setting again.
+#[wasm_bindgen]
+pub fn audio_set_track_interpolation(track_id: &str, mode: &str) -> bool {
+    crate::audio::engine_set_track_interpolation(track_id, mode)
+}
+
+#[wasm_bindgen]
+pub fn audio_set_headroom(headroom_db: f32) { crate::audio::engine_set_headroom(headroom_db) }
+
+#[wasm_bindgen]
+pub fn audio_set_master_gain(gain_db: f32, ramp_ms: f32) { crate::audio::engine_set_master_gain(gain_db, ramp_ms) }
+
+#[wasm_bindgen]
+pub fn audio_set_limiter(enabled: bool, ceiling_db: f32, lookahead_samples: u32, attack_ms: f32, release_ms: f32, knee_db: f32) {
+    crate::audio::engine_set_limiter(enabled, ceiling_db, lookahead_samples, attack_ms, release_ms, knee_db)
+}
+
+/// JSON: { clipCount, limitedSamples, gainReductionDb, maxGainReductionDb }
+#[wasm_bindgen]
+pub fn audio_limiter_stats() -> String {
+    serde_json::to_string(&crate::audio::engine_limiter_stats()).unwrap_or_else(|_| "{}".to_string())
+}
+
+/// JSON: { atSamples, master: { rmsDb, peakDb, truePeakDb }, buses: [{ busId, rmsDb, peakDb }],
+/// duckers: [{ targetBusId, keyBusId, gainReductionDb }] }
+#[wasm_bindgen]
+pub fn audio_meter_snapshot() -> String {
+    serde_json::to_string(&crate::audio::engine_meter_snapshot()).unwrap_or_else(|_| "{}".to_string())
+}
+
+#[wasm_bindgen]
+pub fn audio_reset_limiter_stats() { crate::audio::engine_reset_limiter_stats() }
+
+#[wasm_bindgen]
+pub fn audio_stop(track_id: &str, when_samples: f64, fade_out_ms: f32) -> bool {
+    crate::audio::engine_stop(track_id, when_samples.max(0.0) as u64, fade_out_ms)
+}
+
 // removed: duplicate audio sampling helper (lived in audio.rs)
 
 #[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
 pub fn audio_set_ducker(target_bus: &str, key_bus: &str, threshold_db: f32, ratio: f32, attack_ms: f32, release_ms: f32, max_atten_db: f32, makeup_db: f32) {
     crate::audio::engine_set_ducker(target_bus, key_bus, threshold_db, ratio, attack_ms, release_ms, max_atten_db, makeup_db)
 }
 
+/// Ducker keyed by several buses (`key_buses`: array of ids), with hold, `peak`/`rms` detection
+/// and `max`/`sum` key combination. Replaces the ducker with the same target and keys.
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn audio_set_ducker_keys(target_bus: &str, key_buses: JsArray, threshold_db: f32, ratio: f32, attack_ms: f32, release_ms: f32, hold_ms: f32, max_atten_db: f32, makeup_db: f32, detector: &str, rms_window_ms: f32, combine: &str) -> bool {
+    let keys = strings_from_js(key_buses);
+    let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+    crate::audio::engine_set_ducker_keys(target_bus, &keys, threshold_db, ratio, attack_ms, release_ms, hold_ms, max_atten_db, makeup_db, detector, rms_window_ms, combine)
+}
+
+/// Removes the ducker of `target_bus` with exactly these key buses, or all of its duckers
+/// when `key_buses` is empty.
+#[wasm_bindgen]
+pub fn audio_remove_ducker(target_bus: &str, key_buses: JsArray) -> bool {
+    let keys = strings_from_js(key_buses);
+    let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+    crate::audio::engine_remove_ducker(target_bus, &keys)
+}
+
 #[wasm_bindgen]
 pub fn audio_process_into(out_l: &mut [f32], out_r: &mut [f32]) -> u32 {
     crate::audio::engine_process_into(out_l, out_r)
 }
 
-#[wasm_bindgen]
-pub fn audio_set_markers(track_id: &str, markers: JsArray) -> bool {
+fn markers_from_js(markers: JsArray) -> Vec<usize> {
     let mut v: Vec<usize> = Vec::new();
     for it in markers.values() {
         let vj = it.unwrap_or(JsValue::UNDEFINED);
         if let Some(n) = vj.as_f64() { if n >= 0.0 { v.push(n as usize) } }
     }
-    crate::audio::engine_set_markers(track_id, v)
+    v
+}
+
+fn strings_from_js(items: JsArray) -> Vec<String> {
+    items.values().into_iter().filter_map(|v| v.ok().and_then(|v| v.as_string())).collect()
 }
 
 #[wasm_bindgen]
-pub fn audio_transition(track_id: &str, at: &str, to_asset_id: &str, loop_mode: &str, loop_start: u32, loop_end: i32, xfade_ms: u32) -> bool {
-    crate::audio::engine_transition(track_id, at, to_asset_id, loop_mode, loop_start, loop_end, xfade_ms)
+pub fn audio_set_markers(track_id: &str, markers: JsArray) -> bool {
+    crate::audio::engine_set_markers(track_id, markers_from_js(markers))
+}
+
+/// Tempo map for `nextBeat`/`nextBar`/`bar:N` transitions; `bpm` 0 removes it.
+#[wasm_bindgen]
+pub fn audio_set_tempo(track_id: &str, bpm: f64, beats_per_bar: u32, beat_unit: u32, downbeat_samples: f64) -> bool {
+    crate::audio::engine_set_tempo(track_id, bpm, beats_per_bar, beat_unit, downbeat_samples)
+}
+
+/// JSON: { bar, beat, beatFraction, beatsPerBar, beatUnit, bpm, atSamples, samplesToNextBeat,
+/// samplesToNextBar }, or `null` when the track is not playing or has no tempo map
+#[wasm_bindgen]
+pub fn audio_musical_position(track_id: &str) -> String {
+    serde_json::to_string(&crate::audio::engine_musical_position(track_id)).unwrap_or_else(|_| "null".to_string())
+}
+
+/// Groups tracks as stems sharing one playhead (equal rate and length required).
+#[wasm_bindgen]
+pub fn audio_create_group(group_id: &str, track_ids: JsArray) -> bool {
+    crate::audio::engine_create_group(group_id, strings_from_js(track_ids))
+}
+
+#[wasm_bindgen]
+pub fn audio_set_group_markers(group_id: &str, markers: JsArray) -> bool {
+    crate::audio::engine_set_group_markers(group_id, markers_from_js(markers))
+}
+
+#[wasm_bindgen]
+pub fn audio_group_play_at(group_id: &str, when_samples: f64, offset_samples: u32, loop_mode: &str, loop_start: u32, loop_end: i32, xfade_ms: u32) -> bool {
+    crate::audio::engine_group_play_at(group_id, when_samples.max(0.0) as u64, offset_samples, loop_mode, loop_start, loop_end, xfade_ms)
+}
+
+#[wasm_bindgen]
+pub fn audio_group_stop(group_id: &str, when_samples: f64, fade_out_ms: f32) -> bool {
+    crate::audio::engine_group_stop(group_id, when_samples.max(0.0) as u64, fade_out_ms)
+}
+
+/// `to_asset_ids` holds one asset per stem, in the order the group was created with.
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn audio_group_transition_at(group_id: &str, when_samples: f64, at: &str, to_asset_ids: JsArray, loop_mode: &str, loop_start: u32, loop_end: i32, xfade_ms: u32, fade_ms: f32, fade_align: &str) -> bool {
+    crate::audio::engine_group_transition_at(group_id, when_samples.max(0.0) as u64, at, strings_from_js(to_asset_ids), loop_mode, loop_start, loop_end, xfade_ms, fade_ms, fade_align)
 }
 
-#[derive(Deserialize)]
-struct IdProp {
-    id: Option<String>,
-    name: Option<String>,
+/// `track_ids[i]` gets `gains_db[i]`; all of them change on the same sample.
+#[wasm_bindgen]
+pub fn audio_set_stem_gains_at(group_id: &str, when_samples: f64, track_ids: JsArray, gains_db: Float32Array, ramp_ms: f32) -> bool {
+    let gains = strings_from_js(track_ids).into_iter().zip(gains_db.to_vec()).collect();
+    crate::audio::engine_set_stem_gains_at(group_id, when_samples.max(0.0) as u64, gains, ramp_ms)
+}
+
+#[wasm_bindgen]
+pub fn audio_transition(track_id: &str, at: &str, to_asset_id: &str, loop_mode: &str, loop_start: u32, loop_end: i32, xfade_ms: u32) -> bool {
+    crate::audio::engine_transition(track_id, at, to_asset_id, loop_mode, loop_start, loop_end, xfade_ms)
 }
 
 fn prop_id(props: &Option<serde_json::Map<String, serde_json::Value>>, fallback: &str) -> String {
@@ -127,7 +488,7 @@ fn prop_id(props: &Option<serde_json::Map<String, serde_json::Value>>, fallback:
     fallback.to_string()
 }
 
-fn to_linestring(coords: &Vec<Vec<f64>>) -> Option<LineString<f64>> {
+fn to_linestring(coords: &[Vec<f64>]) -> Option<LineString<f64>> {
     let mut pts = Vec::with_capacity(coords.len());
     for c in coords {
         if c.len() < 2 { return None; }
@@ -136,7 +497,7 @@ fn to_linestring(coords: &Vec<Vec<f64>>) -> Option<LineString<f64>> {
     Some(LineString::from(pts))
 }
 
-fn to_polygon(coords: &Vec<Vec<Vec<f64>>>) -> Option<Polygon<f64>> {
+fn to_polygon(coords: &[Vec<Vec<f64>>]) -> Option<Polygon<f64>> {
     if coords.is_empty() { return None; }
     let outer = to_linestring(&coords[0])?;
     let holes = if coords.len() > 1 {
@@ -145,103 +506,170 @@ fn to_polygon(coords: &Vec<Vec<Vec<f64>>>) -> Option<Polygon<f64>> {
     Some(Polygon::new(outer, holes))
 }
 
-fn point_to_mercator(p: &Point<f64>) -> Point<f64> {
-    // Web Mercator (approx) in meters for small extents
-    let lon = p.x().to_radians();
-    let lat = p.y().to_radians();
-    let r = 6378137.0f64;
-    let x = r * lon;
-    let y = r * (0.5 * (std::f64::consts::PI / 4.0 + lat / 2.0)).tan().ln();
-    Point::new(x, y)
-}
-
-fn linestring_mercator(line: &LineString<f64>) -> LineString<f64> {
-    LineString::from(
-        line
-            .points()
-            .map(|p| {
-                let m = point_to_mercator(&p);
-                (m.x(), m.y())
-            })
-            .collect::<Vec<_>>(),
-    )
-}
-
 #[wasm_bindgen]
 pub fn init_geojson(geojson_text: &str) -> Result<(), JsValue> {
     let parsed = geojson_text
         .parse::<GeoJson>()
         .map_err(|e| JsValue::from_str(&format!("GeoJSON parse error: {e}")))?;
+    let state = state_from_geojson(parsed).map_err(|e| JsValue::from_str(&e))?;
+    STATE.with(|cell| *cell.borrow_mut() = state);
+    Ok(())
+}
 
-    let mut state = State::default();
-
-    match parsed {
-        GeoJson::FeatureCollection(fc) => {
-            for (idx, feat) in fc.features.into_iter().enumerate() {
-                ingest_feature(&mut state, feat, idx);
-            }
-        }
-        GeoJson::Feature(feat) => {
-            ingest_feature(&mut state, feat, 0);
-        }
+fn state_from_geojson(parsed: GeoJson) -> Result<State, String> {
+    let features = match parsed {
+        GeoJson::FeatureCollection(fc) => fc.features,
+        GeoJson::Feature(feat) => vec![feat],
         GeoJson::Geometry(_) => {
-            return Err(JsValue::from_str("Top-level Geometry is not supported; wrap in Feature/FeatureCollection"));
+            return Err("Top-level Geometry is not supported; wrap in Feature/FeatureCollection".to_string());
         }
+    };
+    let mut state = State { frame: LocalFrame::centroid_of(&features), ..State::default() };
+    for (idx, feat) in features.into_iter().enumerate() {
+        ingest_feature(&mut state, feat, idx);
     }
-
-    STATE.with(|cell| *cell.borrow_mut() = state);
-    Ok(())
+    state.build_index();
+    Ok(state)
 }
 
 fn ingest_feature(state: &mut State, feat: Feature, idx: usize) {
     let id = prop_id(&feat.properties, &format!("feature-{idx}"));
-    match feat.geometry {
-        Some(geom) => match geom.value {
+    if let Some(geom) = feat.geometry {
+        match geom.value {
             Value::LineString(coords) => {
                 if let Some(line) = to_linestring(&coords) {
-                    state.roads.push(Road { id, line });
+                    state.roads.push(Road::new(id, line, &state.frame));
                 }
             }
             Value::Polygon(coords) => {
                 if let Some(poly) = to_polygon(&coords) {
-                    state.areas.push(Area { id, poly });
+                    state.areas.push(Area::new(id, poly, &state.frame));
                 }
             }
             Value::MultiLineString(lines) => {
                 for (i, ls) in lines.iter().enumerate() {
                     if let Some(line) = to_linestring(ls) {
-                        state.roads.push(Road { id: format!("{id}:{i}"), line });
+                        state.roads.push(Road::new(format!("{id}:{i}"), line, &state.frame));
                     }
                 }
             }
             Value::MultiPolygon(polys) => {
                 for (i, poly) in polys.iter().enumerate() {
                     if let Some(p) = to_polygon(poly) {
-                        state.areas.push(Area { id: format!("{id}:{i}"), poly: p });
+                        state.areas.push(Area::new(format!("{id}:{i}"), p, &state.frame));
                     }
                 }
             }
             _ => {}
-        },
-        None => {}
+        }
     }
 }
 
 // ----- Core compute helpers (pure functions) -----
+/// Geodesic (WGS84) distance in meters from `p` (lng/lat) to the closest point of `road`, found
+/// in the local frame at `p_m`.
+fn ground_distance(state: &State, p: Point<f64>, p_m: Point<f64>, road: &Road) -> f64 {
+    if let [only] = road.line_m.0[..] { return p.geodesic_distance(&state.frame.inverse(only.into())); }
+    match road.line_m.closest_point(&p_m) {
+        Closest::Intersection(c) | Closest::SinglePoint(c) => p.geodesic_distance(&state.frame.inverse(c)),
+        Closest::Indeterminate => f64::INFINITY,
+    }
+}
+
 fn compute_nearest_road(state: &State, lat: f64, lng: f64) -> (Option<String>, f64) {
     let p = Point::new(lng, lat);
-    let p_m = point_to_mercator(&p);
-    let mut best_id: Option<String> = None;
+    let p_m = state.frame.forward(p);
+    match nearest_road_index(state, p_m).map(|i| &state.roads[i]) {
+        Some(road) => (Some(road.id.clone()), ground_distance(state, p, p_m, road)),
+        None => (None, f64::INFINITY),
+    }
+}
+
+/// Local-frame segments of `road`; a single-vertex road has one of zero length at its point.
+fn road_segments(road: &Road) -> impl Iterator<Item = Line<f64>> + '_ {
+    let point = match road.line_m.0[..] { [only] => Some(Line::new(only, only)), _ => None };
+    road.line_m.lines().chain(point)
+}
+
+/// Local-frame distance from `p_m` to `road`; a single-vertex road is its point.
+fn planar_distance(p_m: Point<f64>, road: &Road) -> f64 {
+    match road.line_m.0[..] {
+        [only] => p_m.euclidean_distance(&Point::from(only)),
+        _ => p_m.euclidean_distance(&road.line_m),
+    }
+}
+
+/// Index into `state.roads` of the road closest to `p_m` (local frame), first one on ties.
+fn nearest_road_index(state: &State, p_m: Point<f64>) -> Option<usize> {
+    // Roads whose nearest segment ties (within rounding) with the closest one, e.g. at a shared
+    // endpoint; the exact distance then decides, first road winning, as a full scan would
+    let mut candidates: Vec<usize> = Vec::new();
+    let mut cutoff = f64::INFINITY;
+    for (seg, d2) in state.road_index.nearest_neighbor_iter_with_distance_2(&p_m) {
+        if cutoff.is_infinite() { cutoff = d2 * (1.0 + 1e-9) + 1e-9; }
+        if d2 > cutoff { break; }
+        candidates.push(seg.data);
+    }
+    candidates.sort_unstable();
+    candidates.dedup();
+    let mut best: Option<usize> = None;
     let mut best_dist: f64 = f64::INFINITY;
-    for road in &state.roads {
-        let line_m = linestring_mercator(&road.line);
-        let d = p_m.euclidean_distance(&line_m);
+    for i in candidates {
+        let d = planar_distance(p_m, &state.roads[i]);
         if d < best_dist {
             best_dist = d;
-            best_id = Some(road.id.clone());
+            best = Some(i);
         }
     }
-    (best_id, best_dist)
+    best
+}
+
+/// Where a position projects onto its nearest road.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RoadSnap {
+    road_id: String,
+    lat: f64,
+    lng: f64,
+    /// Segment `i` runs from vertex `i` to vertex `i + 1`.
+    segment_index: usize,
+    /// Geodesic meters along the road from its first vertex to the snapped point.
+    distance_along_meters: f64,
+    /// `distance_along_meters` over the road length, 0..1.
+    fraction: f64,
+    /// Ground distance from the position to the snapped point.
+    distance_meters: f64,
+}
+
+fn compute_snap_to_road(state: &State, lat: f64, lng: f64) -> Option<RoadSnap> {
+    let p = Point::new(lng, lat);
+    let p_m = state.frame.forward(p);
+    let road = &state.roads[nearest_road_index(state, p_m)?];
+    // First segment at the minimum distance, so a shared vertex snaps to the earlier one
+    let mut best: Option<(usize, f64, f64)> = None;
+    for (i, l) in road_segments(road).enumerate() {
+        let d = l.delta();
+        let len2 = d.x * d.x + d.y * d.y;
+        let t = if len2 > 0.0 { (((p_m.x() - l.start.x) * d.x + (p_m.y() - l.start.y) * d.y) / len2).clamp(0.0, 1.0) } else { 0.0 };
+        let dist = (l.start.x + t * d.x - p_m.x()).hypot(l.start.y + t * d.y - p_m.y());
+        if best.is_none_or(|(_, _, b)| dist < b) { best = Some((i, t, dist)); }
+    }
+    let (segment_index, t, _) = best?;
+    let l = road_segments(road).nth(segment_index)?;
+    let snapped = state.frame.inverse(Point::from(l.start) + (Point::from(l.end) - Point::from(l.start)) * t);
+    let a = road.along_m[segment_index];
+    let b = road.along_m.get(segment_index + 1).copied().unwrap_or(a);
+    let distance_along_meters = a + t * (b - a);
+    let length = road.along_m[road.along_m.len() - 1];
+    Some(RoadSnap {
+        road_id: road.id.clone(),
+        lat: snapped.y(),
+        lng: snapped.x(),
+        segment_index,
+        distance_along_meters,
+        fraction: if length > 0.0 { distance_along_meters / length } else { 0.0 },
+        distance_meters: p.geodesic_distance(&snapped),
+    })
 }
 
 fn compute_nearest_road_id(state: &State, lat: f64, lng: f64) -> Option<String> {
@@ -250,10 +678,13 @@ fn compute_nearest_road_id(state: &State, lat: f64, lng: f64) -> Option<String>
 }
 
 fn compute_area_ids(state: &State, lat: f64, lng: f64) -> Vec<String> {
-    let p = Point::new(lng, lat);
+    let p_m = state.frame.forward(Point::new(lng, lat));
+    let mut hits: Vec<usize> = state.area_index.locate_all_at_point(&[p_m.x(), p_m.y()]).map(|e| e.data).collect();
+    // Feature order, as before the index
+    hits.sort_unstable();
     let mut ids = Vec::new();
-    for area in &state.areas {
-        if area.poly.contains(&p) {
+    for area in hits.into_iter().map(|i| &state.areas[i]) {
+        if area.poly_m.contains(&p_m) {
             ids.push(area.id.clone());
         }
     }
@@ -309,6 +740,52 @@ pub fn query_point(lat: f64, lng: f64) -> String {
     })
 }
 
+/// Sets how close (meters) road endpoints and vertices must be to join into one graph node, and
+/// rebuilds the graph of the loaded roads. Applies to later `init_geojson` calls too.
+#[wasm_bindgen]
+pub fn set_road_snap_tolerance(meters: f64) {
+    let meters = meters.max(0.0);
+    SNAP_TOLERANCE_M.with(|t| t.set(meters));
+    STATE.with(|cell| {
+        let mut state = cell.borrow_mut();
+        let graph = RoadGraph::build(&state.roads, &state.frame, meters);
+        state.graph = graph;
+    })
+}
+
+/// Route graph as JSON: `toleranceMeters`, `nodes` (`id`, `lat`, `lng`, `degree`, `roadIds`) and
+/// `edges` (`id`, `roadId`, `from`, `to`, `startVertex`, `endVertex`, `lengthMeters`).
+#[wasm_bindgen]
+pub fn road_graph_json() -> String {
+    STATE.with(|cell| serde_json::to_string(&cell.borrow().graph).unwrap_or_else(|_| "{}".to_string()))
+}
+
+/// Number of edge ends at `node_id`, or -1 for an unknown node.
+#[wasm_bindgen]
+pub fn road_node_degree(node_id: u32) -> i32 {
+    STATE.with(|cell| cell.borrow().graph.nodes.get(node_id as usize).map_or(-1, |n| n.degree as i32))
+}
+
+/// JSON array of the roads meeting at `node_id` (empty for an unknown node).
+#[wasm_bindgen]
+pub fn road_node_adjacent_roads(node_id: u32) -> String {
+    STATE.with(|cell| {
+        let state = cell.borrow();
+        let roads = state.graph.nodes.get(node_id as usize).map(|n| n.road_ids.as_slice()).unwrap_or(&[]);
+        serde_json::to_string(roads).unwrap_or_else(|_| "[]".to_string())
+    })
+}
+
+/// Closest point on the nearest road as JSON (`roadId`, `lat`, `lng`, `segmentIndex`,
+/// `distanceAlongMeters`, `fraction`, `distanceMeters`), or `null` when there are no roads.
+#[wasm_bindgen]
+pub fn snap_to_road(lat: f64, lng: f64) -> String {
+    STATE.with(|cell| {
+        let state = cell.borrow();
+        serde_json::to_string(&compute_snap_to_road(&state, lat, lng)).unwrap_or_else(|_| "null".to_string())
+    })
+}
+
 #[wasm_bindgen]
 pub fn summarize() -> String {
     STATE.with(|cell| {
@@ -336,17 +813,26 @@ mod tests {
 
     fn state_from_str(s: &str) -> State {
         let parsed = s.parse::<GeoJson>().expect("parse geojson");
-        let mut st = State::default();
-        match parsed {
-            GeoJson::FeatureCollection(fc) => {
-                for (idx, feat) in fc.features.into_iter().enumerate() {
-                    ingest_feature(&mut st, feat, idx);
-                }
-            }
-            GeoJson::Feature(feat) => ingest_feature(&mut st, feat, 0),
-            GeoJson::Geometry(_) => panic!("Top-level Geometry not supported"),
+        state_from_geojson(parsed).expect("features")
+    }
+
+    fn brute_nearest_road(st: &State, lat: f64, lng: f64) -> (Option<String>, f64) {
+        let p = Point::new(lng, lat);
+        let p_m = st.frame.forward(p);
+        let mut best: (Option<&Road>, f64) = (None, f64::INFINITY);
+        for road in &st.roads {
+            let d = planar_distance(p_m, road);
+            if d < best.1 { best = (Some(road), d); }
+        }
+        match best.0 {
+            Some(road) => (Some(road.id.clone()), ground_distance(st, p, p_m, road)),
+            None => (None, f64::INFINITY),
         }
-        st
+    }
+
+    fn brute_area_ids(st: &State, lat: f64, lng: f64) -> Vec<String> {
+        let p_m = st.frame.forward(Point::new(lng, lat));
+        st.areas.iter().filter(|a| a.poly_m.contains(&p_m)).map(|a| a.id.clone()).collect()
     }
 
     #[test]
@@ -372,4 +858,118 @@ mod tests {
         let road = super::compute_nearest_road_id(&st, lat, lng);
         assert!(road.is_some(), "nearest road should exist");
     }
+
+    #[test]
+    fn test_indexed_queries_match_brute_force() {
+        // The level plus a few hundred random roads and overlapping areas around it
+        let mut seed = 0x2545_f491_u64;
+        let mut rnd = move || {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 11) as f64 / (1u64 << 53) as f64
+        };
+        let (lng0, lat0) = (139.8140, 35.7708);
+        let mut features: Vec<String> = Vec::new();
+        let mut vertices: Vec<(f64, f64)> = Vec::new();
+        for i in 0..300 {
+            // Every 25th road is a single vertex
+            let n = if i % 25 == 0 { 1 } else { 2 + (rnd() * 4.0) as usize };
+            let (mut x, mut y) = (lng0 + rnd() * 0.02, lat0 + rnd() * 0.02);
+            let mut pts = Vec::new();
+            for _ in 0..n {
+                pts.push(format!("[{x},{y}]"));
+                vertices.push((y, x));
+                x += (rnd() - 0.5) * 0.002;
+                y += (rnd() - 0.5) * 0.002;
+            }
+            features.push(format!(r#"{{"type":"Feature","properties":{{"id":"r{i}"}},"geometry":{{"type":"LineString","coordinates":[{}]}}}}"#, pts.join(",")));
+        }
+        for i in 0..60 {
+            let (x, y, w, h) = (lng0 + rnd() * 0.02, lat0 + rnd() * 0.02, rnd() * 0.004, rnd() * 0.004);
+            let ring = format!("[[{x},{y}],[{},{y}],[{},{}],[{x},{}],[{x},{y}]]", x + w, x + w * 0.5, y + h, y + h * 0.7);
+            features.push(format!(r#"{{"type":"Feature","properties":{{"id":"a{i}"}},"geometry":{{"type":"Polygon","coordinates":[{ring}]}}}}"#));
+        }
+        let level: serde_json::Value = serde_json::from_str(include_str!("../../../public/routes/level.geojson")).unwrap();
+        for f in level["features"].as_array().unwrap() {
+            if f["geometry"]["type"] == "LineString" {
+                for c in f["geometry"]["coordinates"].as_array().unwrap() { vertices.push((c[1].as_f64().unwrap(), c[0].as_f64().unwrap())); }
+            }
+            features.push(f.to_string());
+        }
+        let st = state_from_str(&format!(r#"{{"type":"FeatureCollection","features":[{}]}}"#, features.join(",")));
+        assert_eq!(st.road_index.size(), st.roads.iter().map(|r| r.line_m.lines().count().max(1)).sum::<usize>());
+
+        let mut checked_areas = 0;
+        let mut single_vertex_hits = 0;
+        for i in 0..2000 {
+            let (lat, lng) = if i % 4 == 0 {
+                // Road vertices: exact ties at shared endpoints
+                vertices[i / 4 % vertices.len()]
+            } else {
+                (lat0 - 0.002 + rnd() * 0.024, lng0 - 0.002 + rnd() * 0.024)
+            };
+            let (id, d) = compute_nearest_road(&st, lat, lng);
+            if id.as_ref().is_some_and(|id| st.roads.iter().any(|r| &r.id == id && r.line_m.0.len() == 1)) { single_vertex_hits += 1; }
+            let (bid, bd) = brute_nearest_road(&st, lat, lng);
+            assert_eq!((id, d), (bid, bd), "nearest road at {lat},{lng}");
+            let areas = compute_area_ids(&st, lat, lng);
+            if !areas.is_empty() { checked_areas += 1; }
+            assert_eq!(areas, brute_area_ids(&st, lat, lng), "areas at {lat},{lng}");
+        }
+        assert!(checked_areas > 100, "too few points inside areas: {checked_areas}");
+        assert!(single_vertex_hits > 0, "no query landed on a single-vertex road");
+    }
+
+    #[test]
+    fn test_road_distance_is_ground_meters() {
+        // A meridian road: the distance east is a parallel arc, the distance north past its end
+        // a meridian arc (WGS84: 0.001 deg of latitude is 110.95 m at 35.77N, 111.56 m at 70N)
+        for (lat, north_m) in [(35.77, 110.95), (70.0, 111.56)] {
+            let st = state_from_str(&format!(r#"{{"type":"Feature","properties":{{"id":"ns"}},"geometry":{{"type":"LineString","coordinates":[[139.81,{}],[139.81,{lat}]]}}}}"#, lat - 0.01));
+            let (id, d) = compute_nearest_road(&st, lat + 0.001, 139.81);
+            assert_eq!(id.as_deref(), Some("ns"));
+            assert!((d - north_m).abs() < 0.02, "north at {lat}: got {d} m");
+            let (_, d) = compute_nearest_road(&st, lat - 0.005, 139.812);
+            let east = Point::new(139.81, lat - 0.005).geodesic_distance(&Point::new(139.812, lat - 0.005));
+            assert!((d - east).abs() < 0.01, "east at {lat}: got {d} m, want {east}");
+        }
+        // Round trip through the local frame
+        let st = state_from_str(include_str!("../../../public/routes/level.geojson"));
+        let p = Point::new(139.8151, 35.7715);
+        let q = st.frame.inverse(st.frame.forward(p));
+        assert!((q.x() - p.x()).abs() < 1e-10 && (q.y() - p.y()).abs() < 1e-10);
+    }
+
+    #[test]
+    fn test_snap_to_road_reports_point_segment_and_progress() {
+        let st = state_from_str(include_str!("../../../public/routes/level.geojson"));
+        let root2 = st.roads.iter().find(|r| r.id == "root2").unwrap();
+        let length = root2.along_m[root2.along_m.len() - 1];
+        // A point 4 m to the side of the middle of root2's first segment
+        let seg = root2.line_m.lines().next().unwrap();
+        let d = seg.delta();
+        let n = Point::new(d.y, -d.x) / d.x.hypot(d.y);
+        let off = st.frame.inverse((Point::from(seg.start) + Point::from(seg.end)) * 0.5 + n * 4.0);
+        let snap = compute_snap_to_road(&st, off.y(), off.x()).unwrap();
+        assert_eq!((snap.road_id.as_str(), snap.segment_index), ("root2", 0));
+        assert!((snap.distance_along_meters - root2.along_m[1] / 2.0).abs() < 0.5, "{snap:?}");
+        assert!((snap.fraction - snap.distance_along_meters / length).abs() < 1e-12);
+        let (_, d) = compute_nearest_road(&st, off.y(), off.x());
+        assert!((snap.distance_meters - d).abs() < 1e-6 && (d - 4.0).abs() < 0.05, "{snap:?} vs {d}");
+
+        // Past root3's southern end: clamps to the last vertex, fraction 1
+        let root3 = st.roads.iter().find(|r| r.id == "root3").unwrap();
+        let end = root3.line_m.0[root3.line_m.0.len() - 1];
+        let beyond = st.frame.inverse(Point::new(end.x, end.y - 15.0));
+        let snap = compute_snap_to_road(&st, beyond.y(), beyond.x()).unwrap();
+        assert_eq!((snap.road_id.as_str(), snap.segment_index), ("root3", root3.line_m.0.len() - 2));
+        assert!((snap.fraction - 1.0).abs() < 1e-12);
+        let v = st.frame.inverse(Point::from(end));
+        assert!((snap.lat - v.y()).abs() < 1e-9 && (snap.lng - v.x()).abs() < 1e-9);
+
+        let json = serde_json::to_value(&snap).unwrap();
+        for key in ["roadId", "lat", "lng", "segmentIndex", "distanceAlongMeters", "fraction", "distanceMeters"] {
+            assert!(json.get(key).is_some(), "missing {key}");
+        }
+        assert_eq!(compute_snap_to_road(&State::default(), 35.0, 139.0), None);
+    }
 }
@@ -0,0 +1,203 @@
+// Route network built from the independent road LineStrings: road endpoints, and vertices that
+// roads share (within the snapping tolerance), become nodes; the road pieces between consecutive
+// nodes become edges.
+
+use crate::{LocalFrame, Road};
+use rstar::primitives::GeomWithData;
+use rstar::{PointDistance, RTree};
+
+pub(crate) const DEFAULT_SNAP_TOLERANCE_M: f64 = 1.0;
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct GraphNode {
+    pub id: usize,
+    pub lat: f64,
+    pub lng: f64,
+    /// Edge ends at this node; a loop edge counts twice.
+    pub degree: usize,
+    /// Roads with an edge ending here, in feature order.
+    pub road_ids: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct GraphEdge {
+    pub id: usize,
+    pub road_id: String,
+    pub from: usize,
+    pub to: usize,
+    /// Vertex range of the road this edge covers, inclusive.
+    pub start_vertex: usize,
+    pub end_vertex: usize,
+    /// Geodesic length of the piece.
+    pub length_meters: f64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct RoadGraph {
+    pub tolerance_meters: f64,
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<GraphEdge>,
+}
+
+impl RoadGraph {
+    pub(crate) fn build(roads: &[Road], frame: &LocalFrame, tolerance_m: f64) -> Self {
+        let tolerance_m = tolerance_m.max(0.0);
+        let verts: Vec<(usize, usize)> = roads.iter().enumerate().flat_map(|(r, road)| (0..road.line_m.0.len()).map(move |v| (r, v))).collect();
+        let pos = |&(r, v): &(usize, usize)| [roads[r].line_m.0[v].x, roads[r].line_m.0[v].y];
+        let is_end = |&(r, v): &(usize, usize)| v == 0 || v + 1 == roads[r].line_m.0.len();
+        let tree = RTree::bulk_load(verts.iter().enumerate().map(|(i, rv)| GeomWithData::new(pos(rv), i)).collect());
+        // Clusters grow around a seed, endpoints seeding first, and only take vertices within the
+        // tolerance of that seed, one per road: a densely sampled road does not chain into one
+        // cluster. A road's own two endpoints may share one, closing a loop. The other interior
+        // vertices around a node are covered by it and neither seed nor join another cluster.
+        let mut cluster: Vec<Option<usize>> = vec![None; verts.len()];
+        let mut covered = vec![false; verts.len()];
+        let mut members: Vec<Vec<usize>> = Vec::new();
+        let seeds = (0..verts.len()).filter(|&i| is_end(&verts[i])).chain((0..verts.len()).filter(|&i| !is_end(&verts[i])));
+        for seed in seeds {
+            if cluster[seed].is_some() || covered[seed] { continue; }
+            let c = members.len();
+            let mut group = vec![seed];
+            cluster[seed] = Some(c);
+            // Nearest first, so each road joins with its closest vertex
+            let at = pos(&verts[seed]);
+            let mut near: Vec<(f64, usize)> = tree.locate_within_distance(at, tolerance_m * tolerance_m).map(|g| (g.distance_2(&at), g.data)).collect();
+            near.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+            for &(_, j) in &near {
+                if cluster[j].is_some() || covered[j] { continue; }
+                let road = verts[j].0;
+                let joins = group.iter().all(|&m| verts[m].0 != road || (is_end(&verts[m]) && is_end(&verts[j])));
+                if joins {
+                    cluster[j] = Some(c);
+                    group.push(j);
+                }
+            }
+            if is_end(&verts[seed]) || group.len() > 1 {
+                for &(_, j) in &near {
+                    if cluster[j].is_none() && !is_end(&verts[j]) { covered[j] = true; }
+                }
+            }
+            members.push(group);
+        }
+        // A vertex is a node when it ends its road or a vertex of another road snaps to it
+        let mut node_of_cluster: Vec<Option<usize>> = vec![None; members.len()];
+        let mut sums: Vec<(f64, f64, usize)> = Vec::new();
+        let mut vert_node: Vec<Option<usize>> = vec![None; verts.len()];
+        for i in 0..verts.len() {
+            let c = match cluster[i] { Some(c) => c, None => continue };
+            if !is_end(&verts[i]) && members[c].len() < 2 { continue; }
+            let n = *node_of_cluster[c].get_or_insert_with(|| {
+                sums.push((0.0, 0.0, 0));
+                sums.len() - 1
+            });
+            let [x, y] = pos(&verts[i]);
+            sums[n] = (sums[n].0 + x, sums[n].1 + y, sums[n].2 + 1);
+            vert_node[i] = Some(n);
+        }
+        let mut nodes: Vec<GraphNode> = sums.iter().enumerate().map(|(id, &(x, y, k))| {
+            let ll = frame.inverse(geo::Point::new(x / k as f64, y / k as f64));
+            GraphNode { id, lat: ll.y(), lng: ll.x(), degree: 0, road_ids: Vec::new() }
+        }).collect();
+
+        // Each road, cut at its node vertices
+        let mut edges: Vec<GraphEdge> = Vec::new();
+        let mut first = 0;
+        for road in roads {
+            let count = road.line_m.0.len();
+            let mut from: Option<(usize, usize)> = None;
+            for v in 0..count {
+                let n = match vert_node[first + v] { Some(n) => n, None => continue };
+                // Vertices a short step apart inside one node (a loop road still gets its edge)
+                if let Some((start, a)) = from.filter(|&(start, a)| a != n || road.along_m[v] - road.along_m[start] > 2.0 * tolerance_m) {
+                    edges.push(GraphEdge {
+                        id: edges.len(),
+                        road_id: road.id.clone(),
+                        from: a,
+                        to: n,
+                        start_vertex: start,
+                        end_vertex: v,
+                        length_meters: road.along_m[v] - road.along_m[start],
+                    });
+                }
+                from = Some((v, n));
+            }
+            first += count;
+        }
+        for e in &edges {
+            for n in [e.from, e.to] {
+                let node = &mut nodes[n];
+                node.degree += 1;
+                if !node.road_ids.contains(&e.road_id) { node.road_ids.push(e.road_id.clone()); }
+            }
+        }
+        RoadGraph { tolerance_meters: tolerance_m, nodes, edges }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph(geojson: &str, tolerance_m: f64) -> RoadGraph {
+        let st = crate::state_from_geojson(geojson.parse().expect("parse geojson")).expect("features");
+        RoadGraph::build(&st.roads, &st.frame, tolerance_m)
+    }
+
+    #[test]
+    fn test_level_roads_join_into_one_route() {
+        let g = graph(include_str!("../../../public/routes/level.geojson"), DEFAULT_SNAP_TOLERANCE_M);
+        // root1 -> root2 -> root3: the two joints snap, leaving a chain of 4 nodes and 3 edges
+        assert_eq!(g.edges.iter().map(|e| (e.road_id.as_str(), e.from, e.to)).collect::<Vec<_>>(), vec![("root1", 0, 1), ("root2", 1, 2), ("root3", 2, 3)]);
+        assert_eq!(g.nodes.iter().map(|n| n.degree).collect::<Vec<_>>(), vec![1, 2, 2, 1]);
+        assert_eq!(g.nodes[1].road_ids, vec!["root1", "root2"]);
+        assert_eq!(g.nodes[2].road_ids, vec!["root2", "root3"]);
+        assert_eq!((g.edges[1].start_vertex, g.edges[1].end_vertex), (0, 4));
+        assert!(g.edges.iter().all(|e| e.length_meters > 10.0));
+
+        // Below the gap between root1's end and root2's start (about 0.2 m) nothing joins
+        let g = graph(include_str!("../../../public/routes/level.geojson"), 0.05);
+        assert_eq!((g.nodes.len(), g.edges.len()), (6, 3));
+        assert!(g.nodes.iter().all(|n| n.degree == 1));
+    }
+
+    #[test]
+    fn test_shared_interior_vertex_splits_roads() {
+        // A crossing at (139.81, 35.77): both roads pass through it, so each is cut in two
+        let g = graph(r#"{"type":"FeatureCollection","features":[
+            {"type":"Feature","properties":{"id":"ew"},"geometry":{"type":"LineString","coordinates":[[139.809,35.77],[139.81,35.77],[139.811,35.77]]}},
+            {"type":"Feature","properties":{"id":"ns"},"geometry":{"type":"LineString","coordinates":[[139.81,35.769],[139.81,35.77],[139.81,35.771]]}},
+            {"type":"Feature","properties":{"id":"bend"},"geometry":{"type":"LineString","coordinates":[[139.811,35.77],[139.812,35.7705],[139.813,35.77]]}}]}"#, 1.0);
+        assert_eq!(g.edges.len(), 5);
+        let hub = g.nodes.iter().find(|n| n.degree == 4).expect("crossing node");
+        assert_eq!(hub.road_ids, vec!["ew", "ns"]);
+        assert!((hub.lat - 35.77).abs() < 1e-9 && (hub.lng - 139.81).abs() < 1e-9);
+        // The bend's middle vertex is not shared, so "bend" stays one edge joined to "ew"
+        let bend: Vec<&GraphEdge> = g.edges.iter().filter(|e| e.road_id == "bend").collect();
+        assert_eq!((bend.len(), bend[0].start_vertex, bend[0].end_vertex), (1, 0, 2));
+        assert_eq!(g.nodes[bend[0].from].road_ids, vec!["ew", "bend"]);
+        assert_eq!(g.nodes.len(), 6);
+    }
+
+    #[test]
+    fn test_densely_sampled_roads_do_not_collapse() {
+        // Two 20 m roads sampled every ~0.1 m (0.000001 deg), well under the 1 m tolerance,
+        // meeting end to start
+        let line = |from: f64, to: f64| {
+            let n = ((to - from) / 0.000001).round() as usize;
+            (0..=n).map(|k| format!("[{},35.77]", from + k as f64 * 0.000001)).collect::<Vec<_>>().join(",")
+        };
+        let g = graph(&format!(r#"{{"type":"FeatureCollection","features":[
+            {{"type":"Feature","properties":{{"id":"a"}},"geometry":{{"type":"LineString","coordinates":[{}]}}}},
+            {{"type":"Feature","properties":{{"id":"b"}},"geometry":{{"type":"LineString","coordinates":[{}]}}}}]}}"#,
+            line(139.81, 139.81022), line(139.81022, 139.81044)), 1.0);
+        assert_eq!(g.edges.iter().map(|e| (e.road_id.as_str(), e.from, e.to)).collect::<Vec<_>>(), vec![("a", 0, 1), ("b", 1, 2)]);
+        assert_eq!(g.nodes.iter().map(|n| n.degree).collect::<Vec<_>>(), vec![1, 2, 1]);
+        assert_eq!((g.edges[0].start_vertex, g.edges[0].end_vertex), (0, 220));
+        // The joint stays where the roads meet, not smeared along them
+        assert!((g.nodes[1].lng - 139.81022).abs() < 1e-9, "{}", g.nodes[1].lng);
+        assert!(g.edges.iter().all(|e| (e.length_meters - 19.9).abs() < 0.3), "{:?}", g.edges);
+    }
+}